@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitInt};
+
+/// Derive `bitsock::protocol::Protocol` for an enum, mapping every variant to a
+/// `Packet::Identified` with a stable id.
+///
+/// Ids can be set with `#[bitsock(id = N)]`, variants without one take the id of the previous
+/// variant plus one, starting from 0. Variants can carry no data or a single unnamed field
+/// implementing `bitsock::protocol::Payload`.
+#[proc_macro_derive(BitsockProtocol, attributes(bitsock))]
+pub fn derive_protocol(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "BitsockProtocol can only be derived for enums",
+            ))
+        }
+    };
+
+    let mut ids = HashMap::new();
+    let mut next_id = 0u32;
+    let mut id_arms = Vec::new();
+    let mut encode_arms = Vec::new();
+    let mut decode_arms = Vec::new();
+
+    for variant in &data.variants {
+        let id = match variant_id(variant)? {
+            Some(id) => id,
+            None => next_id,
+        };
+        if let Some(previous) = ids.insert(id, variant.ident.clone()) {
+            return Err(Error::new_spanned(
+                &variant.ident,
+                format!("id {} is already used by `{}`", id, previous),
+            ));
+        }
+        next_id = id.wrapping_add(1);
+
+        let ident = &variant.ident;
+        match &variant.fields {
+            Fields::Unit => {
+                id_arms.push(quote! { Self::#ident => #id });
+                encode_arms.push(quote! {
+                    Self::#ident => ::bitsock::Packet::Identified(#id, ::std::vec::Vec::new())
+                });
+                decode_arms.push(quote! {
+                    #id if data.is_empty() => Ok(Self::#ident),
+                    #id => Err(::bitsock::protocol::ProtocolError::InvalidPayload(#id))
+                });
+            }
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                id_arms.push(quote! { Self::#ident(_) => #id });
+                encode_arms.push(quote! {
+                    Self::#ident(payload) => ::bitsock::Packet::Identified(
+                        #id,
+                        ::bitsock::protocol::Payload::to_bytes(payload),
+                    )
+                });
+                decode_arms.push(quote! {
+                    #id => <#ty as ::bitsock::protocol::Payload>::from_bytes(&data)
+                        .map(Self::#ident)
+                        .ok_or(::bitsock::protocol::ProtocolError::InvalidPayload(#id))
+                });
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    variant,
+                    "BitsockProtocol variants must have no fields or a single unnamed field",
+                ))
+            }
+        }
+    }
+
+    let reserved_checks = ids.keys().map(|id| {
+        quote! { const _: () = assert!(#id < ::bitsock::RESERVED_ID_START, "id is reserved by bitsock"); }
+    });
+
+    Ok(quote! {
+        #(#reserved_checks)*
+
+        impl #impl_generics ::bitsock::protocol::Protocol for #name #ty_generics #where_clause {
+            fn id(&self) -> u32 {
+                match self {
+                    #(#id_arms,)*
+                }
+            }
+
+            fn to_packet(&self) -> ::bitsock::Packet {
+                match self {
+                    #(#encode_arms,)*
+                }
+            }
+
+            fn from_packet(
+                packet: ::bitsock::Packet,
+            ) -> ::std::result::Result<Self, ::bitsock::protocol::ProtocolError> {
+                match packet {
+                    ::bitsock::Packet::Identified(id, data) => match id {
+                        #(#decode_arms,)*
+                        id => Err(::bitsock::protocol::ProtocolError::UnknownId(id)),
+                    },
+                    packet => Err(::bitsock::protocol::ProtocolError::NotIdentified(packet)),
+                }
+            }
+        }
+    })
+}
+
+/// Returns the id set with `#[bitsock(id = N)]` on a variant, if any.
+fn variant_id(variant: &syn::Variant) -> Result<Option<u32>, Error> {
+    let mut id = None;
+
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("bitsock"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u32>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported bitsock attribute, expected `id = N`"))
+            }
+        })?;
+    }
+
+    Ok(id)
+}
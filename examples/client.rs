@@ -14,7 +14,7 @@ fn main() {
 
     loop {
         // Try to send a packet containing just an i32.
-        if let Err(_) = client.send(Packet::I32(5)) {
+        if client.send(Packet::I32(5)).is_err() {
             eprintln!("Failed to send packet");
         } else {
             // If the packet can be sent, then listen to the server and wait for a Packet.
@@ -4,7 +4,7 @@ Example: Logging
 This is a simple server application with custom logging, it can communicate with the Client example.
 */
 
-use bitsock::{server::ServerBuilder, LogStage, Packet};
+use bitsock::{server::ServerBuilder, Error, LogStage, Packet};
 
 fn main() {
     // Create the server object and bind it to the 4444 port.
@@ -25,14 +25,20 @@ fn main() {
                     c.send(Packet::String("Hello There!".to_string())).unwrap();
                     true
                 }
+                // The Client closed the connection.
+                Err(Error::Disconnected) => false,
                 // If it fails, disconnect the Client and print the error.
                 Err(e) => {
-                    c.disconnect().unwrap();
-                    println!("Client {} disconnected for {:?}!", c.address(), e);
+                    let _ = c.disconnect();
+                    println!("Client {} dropped for {}!", c.address(), e);
                     false
                 }
             } {}
         }))
+        // Print the client address once its connection is over.
+        .disconnect_handler(Box::new(|address| {
+            println!("Client {} disconnected!", address);
+        }))
         // Setup the custom logger
         .log_handler(Box::new(|stage, level, message| {
             if let LogStage::SERVER = stage {
@@ -0,0 +1,564 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+};
+
+use crate::{
+    hello::{self, Capabilities, Hello, HELLO_TIMEOUT},
+    limited_frame_length,
+    server::{ErrorHandler, LogHandler},
+    truncated, Error, LogLevel, LogStage, Packet, FRAME_HEADER_SIZE, MAX_FRAME_SIZE, PING_ID,
+    PONG_ID,
+};
+
+/// Delay before accepting again after the listener failed, doubled while it keeps failing.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Future returned by an [AsyncClientHandler].
+pub type HandlerFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Handler called with every [AsyncLogicalClient] that connects to the asynchronous server.
+pub type AsyncClientHandler = Arc<dyn Fn(AsyncLogicalClient) -> HandlerFuture + Send + Sync>;
+
+/// Write a [Packet] to an asynchronous stream as a single frame, returning the number of bytes written.
+/// Fails if the payload is bigger than `max`.
+async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    packet: &Packet,
+    max: usize,
+) -> Result<usize, Error> {
+    let frame = packet.checked_frame()?;
+    let size = frame.len() - FRAME_HEADER_SIZE;
+    if size > max {
+        return Err(Error::FrameTooLarge { size, max });
+    }
+
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+
+    Ok(frame.len())
+}
+
+/// Read exactly one frame from an asynchronous stream and decode it into a [Packet].
+/// Fails if the payload is bigger than `max`.
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R, max: usize) -> Result<Packet, Error> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    if reader.read(&mut header[..1]).await? == 0 {
+        return Err(Error::Disconnected);
+    }
+    reader
+        .read_exact(&mut header[1..])
+        .await
+        .map_err(truncated)?;
+
+    let length = limited_frame_length(header, max)?;
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.map_err(truncated)?;
+
+    Packet::decode_body(&body)
+}
+
+/// Read the next [Packet] that is not a heartbeat, answering the pings of the peer.
+async fn read_answering(stream: &mut TcpStream, max: usize) -> Result<Packet, Error> {
+    loop {
+        match read_packet(stream, max).await? {
+            Packet::Identified(PING_ID, _) => {
+                write_packet(stream, &Packet::Identified(PONG_ID, Vec::new()), max).await?;
+            }
+            Packet::Identified(PONG_ID, _) => {}
+            packet => return Ok(packet),
+        }
+    }
+}
+
+/// Runs `future` until the server starts shutting down, returns [None] if it did first.
+async fn until_stopped<F: Future>(
+    stopping: &mut watch::Receiver<Option<Duration>>,
+    future: F,
+) -> Option<F::Output> {
+    let mut stopped = pin!(stopping.wait_for(Option::is_some));
+    let mut future = pin!(future);
+
+    std::future::poll_fn(|cx| {
+        // The server is also gone once its sender is dropped.
+        if stopped.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        future.as_mut().poll(cx).map(Some)
+    })
+    .await
+}
+
+/// Reads the hello of the peer, giving up after `timeout`.
+async fn read_hello(stream: &mut TcpStream, timeout: Duration) -> Result<Packet, Error> {
+    tokio::time::timeout(timeout, read_packet(stream, MAX_FRAME_SIZE))
+        .await
+        .map_err(|_| Error::Timeout)?
+}
+
+/// Client side of the hello exchange: sends the hello, then waits for the one of the server.
+/// The asynchronous client cannot authenticate, so servers requiring it are refused.
+async fn connect_hello(stream: &mut TcpStream, hello: &Hello) -> Result<Hello, Error> {
+    write_packet(stream, &hello.to_packet(), MAX_FRAME_SIZE).await?;
+
+    let agreed = hello::check_answer(hello, &read_hello(stream, HELLO_TIMEOUT).await?)?;
+    if agreed.auth_required {
+        return Err(Error::Handshake(
+            "the server requires authentication".to_string(),
+        ));
+    }
+
+    Ok(agreed)
+}
+
+/// Server side of the hello exchange: waits for the hello of the client for at most `timeout`,
+/// then answers with its own or refuses the connection.
+async fn accept_hello(
+    stream: &mut TcpStream,
+    hello: &Hello,
+    timeout: Duration,
+) -> Result<Hello, Error> {
+    let packet = read_hello(stream, timeout).await?;
+
+    match hello::check_hello(hello, &packet) {
+        Ok(agreed) => {
+            write_packet(stream, &hello.to_packet(), MAX_FRAME_SIZE).await?;
+            Ok(agreed)
+        }
+        Err(reason) => {
+            let _ = write_packet(stream, &hello::rejection(&reason), MAX_FRAME_SIZE).await;
+            Err(Error::Handshake(reason))
+        }
+    }
+}
+
+/// Asynchronous physical client data structure.
+pub struct AsyncClient {
+    stream: TcpStream,
+    negotiated: Hello,
+}
+
+impl AsyncClient {
+    /// Connect the client to a server with given ip and port and return the client object.
+    pub async fn connect(address: &str, port: u16) -> Result<Self, Error> {
+        Self::connect_with(address, port, Hello::default()).await
+    }
+
+    /// Connect the client to a server with given ip and port, announcing `hello` instead of the
+    /// default [Hello].
+    pub async fn connect_with(address: &str, port: u16, hello: Hello) -> Result<Self, Error> {
+        let address = format!("{}:{}", address, port);
+
+        let mut stream = match TcpStream::connect(&address).await {
+            Ok(stream) => stream,
+            Err(source) => return Err(Error::Connect { address, source }),
+        };
+
+        Ok(Self {
+            negotiated: connect_hello(&mut stream, &hello).await?,
+            stream,
+        })
+    }
+
+    /// Get the settings agreed with the server when connecting.
+    pub fn negotiated(&self) -> &Hello {
+        &self.negotiated
+    }
+
+    /// Send a [Packet] to the server.
+    pub async fn send(&mut self, packet: Packet) -> Result<usize, Error> {
+        let max = self.negotiated.capabilities.max_frame_size;
+        write_packet(&mut self.stream, &packet, max).await
+    }
+
+    /// Listen to a [Packet] from the server, answering its pings in the meantime.
+    /// Returns [Error::Disconnected] once the server closed the connection.
+    pub async fn read(&mut self) -> Result<Packet, Error> {
+        let max = self.negotiated.capabilities.max_frame_size;
+        read_answering(&mut self.stream, max).await
+    }
+
+    /// Close the connection with the server.
+    pub async fn disconnect(&mut self) -> Result<(), Error> {
+        Ok(self.stream.shutdown().await?)
+    }
+}
+
+/// Asynchronous logical client data structure.
+pub struct AsyncLogicalClient {
+    address: String,
+    stream: TcpStream,
+    negotiated: Hello,
+    stopping: watch::Receiver<Option<Duration>>,
+}
+
+impl AsyncLogicalClient {
+    /// Send a [Packet] to the client.
+    pub async fn send(&mut self, packet: Packet) -> Result<usize, Error> {
+        let max = self.negotiated.capabilities.max_frame_size;
+        write_packet(&mut self.stream, &packet, max).await
+    }
+
+    /// Listen to a [Packet] from the client, answering its pings in the meantime.
+    /// Returns [Error::Disconnected] once the client closed the connection or the server is
+    /// shutting down.
+    pub async fn read(&mut self) -> Result<Packet, Error> {
+        let max = self.negotiated.capabilities.max_frame_size;
+        until_stopped(&mut self.stopping, read_answering(&mut self.stream, max))
+            .await
+            .unwrap_or(Err(Error::Disconnected))
+    }
+
+    /// Get the address of the client.
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    /// Get the settings agreed with the client when it connected.
+    pub fn negotiated(&self) -> &Hello {
+        &self.negotiated
+    }
+
+    /// Returns true once the server is shutting down, the handler should finish its work and return.
+    pub fn is_shutting_down(&self) -> bool {
+        self.stopping.borrow().is_some()
+    }
+
+    /// Close the connection with the client.
+    pub async fn disconnect(&mut self) -> Result<(), Error> {
+        Ok(self.stream.shutdown().await?)
+    }
+}
+
+/// Asynchronous physical server data structure, every client is handled by its own tokio task.
+/// Clients must complete the [Hello] exchange before the `client handler` sees them.
+pub struct AsyncServer {
+    pub address: String,
+    pub port: u16,
+    hello: Hello,
+    hello_timeout: Duration,
+    listener: Mutex<Option<TcpListener>>,
+    error_handler: Option<ErrorHandler>,
+    client_handler: AsyncClientHandler,
+    log_handler: Option<Arc<LogHandler>>,
+    /// Set to the grace period given to the handlers once the server is shutting down.
+    stopping: Arc<watch::Sender<Option<Duration>>>,
+}
+
+/// Handle used to stop a running [AsyncServer] from any thread or task.
+#[derive(Clone)]
+pub struct AsyncServerHandle {
+    stopping: Arc<watch::Sender<Option<Duration>>>,
+}
+
+impl AsyncServerHandle {
+    /// Gracefully stop the server.
+    ///
+    /// The server stops accepting connections and [AsyncLogicalClient::read] returns
+    /// [Error::Disconnected], so handlers can finish. Once every handler returned, or after
+    /// `timeout`, the remaining handlers are cancelled and [AsyncServer::run] returns.
+    pub fn shutdown(&self, timeout: Duration) {
+        self.stopping.send_if_modified(|stopping| {
+            let first = stopping.is_none();
+            stopping.get_or_insert(timeout);
+            first
+        });
+    }
+
+    /// Returns true once [AsyncServerHandle::shutdown] has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.stopping.borrow().is_some()
+    }
+}
+
+impl AsyncServer {
+    /// Returns an [AsyncServerHandle] that can be used to stop the server.
+    pub fn handle(&self) -> AsyncServerHandle {
+        AsyncServerHandle {
+            stopping: self.stopping.clone(),
+        }
+    }
+
+    /// Bind the server without accepting clients yet, so [AsyncServer::local_addr] is known before
+    /// [AsyncServer::run] is called. [AsyncServer::run] binds the server itself otherwise.
+    pub async fn listen(&self) -> Result<(), Error> {
+        if self.listener.lock().unwrap().is_none() {
+            let listener = self.bind().await?;
+            *self.listener.lock().unwrap() = Some(listener);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address [AsyncServer::listen] bound the server to, to find the port picked for
+    /// port `0`. Returns [None] before [AsyncServer::listen] and once [AsyncServer::run] started.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        let listener = self.listener.lock().unwrap();
+        listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Start the server execution, this will accept clients until the listener fails to bind or
+    /// [AsyncServerHandle::shutdown] is called. Must be called from within a tokio runtime.
+    pub async fn run(&self) {
+        self.log(LogLevel::INFO, "Starting server");
+
+        let listened = self.listener.lock().unwrap().take();
+        let listener = match listened {
+            Some(listener) => listener,
+            None => match self.bind().await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    self.handle_error(e);
+                    return;
+                }
+            },
+        };
+
+        self.log(LogLevel::INFO, "Server started, listening for connections.");
+
+        let mut stopping = self.stopping.subscribe();
+        let mut clients = JoinSet::new();
+        let mut backoff = None;
+
+        while let Some(accepted) = until_stopped(&mut stopping, listener.accept()).await {
+            // Reaps the handlers that returned, so they do not pile up until the server stops.
+            while clients.try_join_next().is_some() {}
+
+            match accepted {
+                Ok((mut stream, address)) => {
+                    backoff = None;
+                    let hello = self.hello.clone();
+                    let hello_timeout = self.hello_timeout;
+                    let handler = self.client_handler.clone();
+                    let log_handler = self.log_handler.clone();
+                    let stopping = stopping.clone();
+
+                    clients.spawn(async move {
+                        match accept_hello(&mut stream, &hello, hello_timeout).await {
+                            Ok(negotiated) => {
+                                let client = AsyncLogicalClient {
+                                    address: address.to_string(),
+                                    stream,
+                                    negotiated,
+                                    stopping,
+                                };
+                                handler(client).await;
+                            }
+                            Err(e) => log(
+                                &log_handler,
+                                LogLevel::WARN,
+                                &format!("Handshake with client {} failed: {}.", address, e),
+                            ),
+                        }
+                    });
+                }
+                Err(e) => {
+                    // Failures such as running out of file descriptors last, so only the first
+                    // one of a streak is reported and accepting slows down until it works again.
+                    let delay = match backoff {
+                        Some(delay) => MAX_ACCEPT_BACKOFF.min(delay * 2),
+                        None => {
+                            self.handle_error(Error::Io(e));
+                            ACCEPT_BACKOFF
+                        }
+                    };
+                    backoff = Some(delay);
+                    until_stopped(&mut stopping, tokio::time::sleep(delay)).await;
+                }
+            }
+        }
+
+        let grace = (*stopping.borrow()).unwrap_or_default();
+        let finished = tokio::time::timeout(grace, async {
+            while clients.join_next().await.is_some() {}
+        })
+        .await;
+        if finished.is_err() {
+            clients.shutdown().await;
+        }
+
+        self.log(LogLevel::INFO, "Server stopped.");
+    }
+
+    /// Internal function, binds the TCP listener.
+    async fn bind(&self) -> Result<TcpListener, Error> {
+        let address = format!("{}:{}", self.address, self.port);
+        match TcpListener::bind(&address).await {
+            Ok(listener) => Ok(listener),
+            Err(source) => Err(Error::Bind { address, source }),
+        }
+    }
+
+    /// Internal function, used to handle errors propagated by the server.
+    fn handle_error(&self, error: Error) {
+        if let Some(handler) = &self.error_handler {
+            handler(error);
+        } else {
+            println!("{}", crate::error::report(&error));
+        }
+    }
+
+    /// Log a message from the physical server.
+    pub fn log(&self, level: LogLevel, message: &str) {
+        log(&self.log_handler, level, message);
+    }
+}
+
+/// Passes a log to the server `logger`, printed to stdout without one.
+fn log(handler: &Option<Arc<LogHandler>>, level: LogLevel, message: &str) {
+    if let Some(handler) = handler {
+        handler(LogStage::SERVER, level, message);
+    } else {
+        println!("[SERVER][{:?}]: {}", level, message);
+    }
+}
+
+/// Asynchronous server builder object, works like [ServerBuilder](crate::server::ServerBuilder).
+/// ```
+/// use bitsock::{asynchronous::AsyncServerBuilder, Packet};
+///
+/// let server = AsyncServerBuilder::new()
+///     .port(4444)
+///     .client_handler(|mut c| async move {
+///         while let Ok(packet) = c.read().await {
+///             let _ = c.send(packet).await;
+///         }
+///     })
+///     .build();
+/// ```
+pub struct AsyncServerBuilder {
+    address: String,
+    port: u16,
+    hello: Hello,
+    hello_timeout: Duration,
+    error_handler: Option<ErrorHandler>,
+    client_handler: AsyncClientHandler,
+    log_handler: Option<LogHandler>,
+}
+
+impl AsyncServerBuilder {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            address: "0.0.0.0".to_string(),
+            port: 4444,
+            hello: Hello::default(),
+            hello_timeout: HELLO_TIMEOUT,
+            error_handler: None,
+            client_handler: Arc::new(|c| {
+                Box::pin(async move { println!("{} connected.", c.address()) })
+            }),
+            log_handler: None,
+        }
+    }
+
+    /// Sets the server address.
+    pub fn address(self, address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the server port.
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Sets the application protocol announced in the [Hello], clients speaking another name or
+    /// version are refused.
+    pub fn protocol(self, name: &str, version: u32) -> Self {
+        Self {
+            hello: Hello {
+                protocol: name.to_string(),
+                protocol_version: version,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets the capabilities announced in the [Hello].
+    pub fn capabilities(self, capabilities: Capabilities) -> Self {
+        Self {
+            hello: Hello {
+                capabilities,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets how long a client has to send its [Hello] before it is disconnected,
+    /// [HELLO_TIMEOUT] by default.
+    pub fn hello_timeout(self, hello_timeout: Duration) -> Self {
+        Self {
+            hello_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the server `error handler`
+    pub fn error_handler(self, handler: ErrorHandler) -> Self {
+        Self {
+            error_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Sets the server `client handler`, the returned future is spawned on a new task.
+    pub fn client_handler<F, Fut>(self, handler: F) -> Self
+    where
+        F: Fn(AsyncLogicalClient) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            client_handler: Arc::new(move |c| Box::pin(handler(c))),
+            ..self
+        }
+    }
+
+    /// Sets the server `logger`
+    pub fn log_handler(self, handler: LogHandler) -> Self {
+        Self {
+            log_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Build the server object.
+    pub fn build(self) -> AsyncServer {
+        AsyncServer {
+            address: self.address,
+            port: self.port,
+            hello: self.hello,
+            hello_timeout: self.hello_timeout,
+            listener: Mutex::new(None),
+            error_handler: self.error_handler,
+            client_handler: self.client_handler,
+            log_handler: self.log_handler.map(Arc::new),
+            stopping: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+impl Default for AsyncServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+#[cfg(feature = "hmac")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "hmac")]
+use sha2::Sha256;
+
+use crate::{client::Client, server::LogicalClient, Error, Packet, AUTH_ID, AUTH_RESULT_ID};
+
+/// Handler authenticating every client before the server `client handler` sees it, registered
+/// with [ServerBuilder::auth_handler](crate::server::ServerBuilder::auth_handler).
+/// An [Err] rejects the client, the reason is sent to it before the connection is closed.
+/// Handlers should read with [LogicalClient::read_within] and [LogicalClient::auth_timeout], so
+/// a silent client cannot hold its slot.
+pub type AuthHandler = Box<dyn Fn(&mut LogicalClient) -> Result<(), String> + Send + Sync>;
+
+/// Handler running the client side of the handshake, registered with
+/// [ClientBuilder::auth_handler](crate::client::ClientBuilder::auth_handler).
+/// Handlers should read with [Client::read_within] and [AUTH_TIMEOUT].
+pub type ClientAuthHandler = Box<dyn Fn(&mut Client) -> Result<(), String> + Send + Sync>;
+
+/// How long each step of the authentication exchange can wait for the peer, unless the server
+/// sets another [ServerBuilder::auth_timeout](crate::server::ServerBuilder::auth_timeout).
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ACCEPTED: u8 = 0;
+const REJECTED: u8 = 1;
+
+/// Size in bytes of the random challenge sent by [hmac_challenge].
+#[cfg(feature = "hmac")]
+const CHALLENGE_SIZE: usize = 32;
+
+/// Server side of a token handshake: the client must send `expected`, see [send_token].
+/// ```
+/// use bitsock::{auth, server::ServerBuilder};
+///
+/// let server = ServerBuilder::new()
+///     .port(4444)
+///     .auth_handler(auth::token("secret token"))
+///     .build();
+/// ```
+pub fn token(expected: impl Into<String>) -> AuthHandler {
+    let expected = expected.into();
+
+    Box::new(move |client| {
+        let token = read_auth(client.read_within(client.auth_timeout()))?;
+        if constant_time_eq(&token, expected.as_bytes()) {
+            Ok(())
+        } else {
+            Err("invalid token".to_string())
+        }
+    })
+}
+
+/// Client side of a token handshake, sends the token checked by [token].
+pub fn send_token(token: impl Into<String>) -> ClientAuthHandler {
+    let token = token.into();
+
+    Box::new(move |client| {
+        client
+            .send(Packet::Identified(AUTH_ID, token.as_bytes().to_vec()))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// Server side of a challenge-response handshake: the client must answer a random challenge with
+/// its HMAC-SHA256 under `secret`, so the secret itself never goes over the connection.
+/// See [hmac_response].
+#[cfg(feature = "hmac")]
+pub fn hmac_challenge(secret: impl Into<Vec<u8>>) -> AuthHandler {
+    let secret = secret.into();
+
+    Box::new(move |client| {
+        let mut challenge = [0; CHALLENGE_SIZE];
+        getrandom::fill(&mut challenge).map_err(|e| e.to_string())?;
+        client
+            .send(Packet::Identified(AUTH_ID, challenge.to_vec()))
+            .map_err(|e| e.to_string())?;
+
+        let response = read_auth(client.read_within(client.auth_timeout()))?;
+        hmac(&secret, &challenge)
+            .verify_slice(&response)
+            .map_err(|_| "invalid challenge response".to_string())
+    })
+}
+
+/// Client side of a challenge-response handshake, answers the challenge sent by [hmac_challenge].
+#[cfg(feature = "hmac")]
+pub fn hmac_response(secret: impl Into<Vec<u8>>) -> ClientAuthHandler {
+    let secret = secret.into();
+
+    Box::new(move |client| {
+        let challenge = read_auth(client.read_within(AUTH_TIMEOUT))?;
+        let response = hmac(&secret, &challenge).finalize().into_bytes();
+
+        client
+            .send(Packet::Identified(AUTH_ID, response.to_vec()))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[cfg(feature = "hmac")]
+fn hmac(secret: &[u8], challenge: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(challenge);
+
+    mac
+}
+
+/// Returns the body of an authentication packet read from the peer.
+fn read_auth(packet: Result<Packet, Error>) -> Result<Vec<u8>, String> {
+    match packet {
+        Ok(Packet::Identified(AUTH_ID, body)) => Ok(body),
+        Ok(Packet::Identified(AUTH_RESULT_ID, body)) => Err(reason(&body)),
+        Ok(_) => Err("expected an authentication packet".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Compares two byte strings in a time that does not depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Builds the packet telling the client whether it was accepted.
+pub(crate) fn result_packet(result: &Result<(), String>) -> Packet {
+    let body = match result {
+        Ok(()) => vec![ACCEPTED],
+        Err(reason) => {
+            let mut body = vec![REJECTED];
+            body.extend_from_slice(reason.as_bytes());
+            body
+        }
+    };
+
+    Packet::Identified(AUTH_RESULT_ID, body)
+}
+
+/// Returns the reason carried by the result of a rejected authentication.
+fn reason(body: &[u8]) -> String {
+    match body.split_first() {
+        Some((_, reason)) => String::from_utf8_lossy(reason).into_owned(),
+        None => "empty authentication result".to_string(),
+    }
+}
+
+/// Runs the client side of the handshake, then waits for the server to accept or reject it.
+pub(crate) fn authenticate(client: &mut Client, handler: &ClientAuthHandler) -> Result<(), Error> {
+    handler(client).map_err(Error::AuthRejected)?;
+
+    match client.read_within(AUTH_TIMEOUT)? {
+        Packet::Identified(AUTH_RESULT_ID, body) if body.first() == Some(&ACCEPTED) => Ok(()),
+        Packet::Identified(AUTH_RESULT_ID, body) => Err(Error::AuthRejected(reason(&body))),
+        _ => Err(Error::AuthRejected(
+            "expected the authentication result".to_string(),
+        )),
+    }
+}
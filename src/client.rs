@@ -1,49 +1,647 @@
 use std::{
-    io::{Read, Write},
-    net::{Shutdown, TcpStream},
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io::ErrorKind,
+    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
 };
+#[cfg(unix)]
+use std::{os::unix::net::UnixStream, path::Path};
 
-use crate::{ConnectionError, Packet, ReadingError};
+#[cfg(feature = "rustls")]
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, StreamOwned};
+
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+
+#[cfg(feature = "serde")]
+use crate::message::{MessageCodec, MessageError};
+#[cfg(feature = "websocket")]
+use crate::websocket;
+use crate::{
+    auth::{self, ClientAuthHandler},
+    hello::{self, Capabilities, Hello, HELLO_TIMEOUT},
+    server::LogHandler,
+    split::{self, PacketReader, PacketWriter},
+    stream::{Connection, ConnectionOptions, Heartbeat, Stream},
+    Error, LogLevel, LogStage, Packet,
+};
 
 /// Physical client data structure.
 pub struct Client {
-    stream: TcpStream,
+    connection: Connection,
+    negotiated: Hello,
+    log_handler: Option<Arc<LogHandler>>,
+    #[cfg(feature = "serde")]
+    codec: MessageCodec,
 }
 
 impl Client {
     /// Connect the client to a server with given ip and port and return the client object.
-    pub fn connect(address: &str, port: u16) -> Result<Self, ConnectionError> {
-        match TcpStream::connect(format!("{}:{}", address, port)) {
-            Ok(stream) => Ok(Self { stream }),
-            Err(e) => Err(ConnectionError::Client(e.to_string())),
-        }
+    /// Use [ClientBuilder] to configure timeouts, retries and socket options.
+    pub fn connect(address: &str, port: u16) -> Result<Self, Error> {
+        ClientBuilder::new().address(address).port(port).connect()
+    }
+
+    /// Connect the client to a server with given address and port over TLS and return the client object.
+    /// The address is also the name the server certificate is verified against.
+    #[cfg(feature = "rustls")]
+    pub fn connect_tls(address: &str, port: u16, config: Arc<ClientConfig>) -> Result<Self, Error> {
+        ClientBuilder::new()
+            .address(address)
+            .port(port)
+            .tls(config)
+            .connect()
+    }
+
+    /// Connect the client to a server listening on a Unix domain socket at `path`, see
+    /// [ServerBuilder::unix_path](crate::server::ServerBuilder::unix_path).
+    /// Use [ClientBuilder::connect_unix] to configure the handshake and timeouts.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        ClientBuilder::new().connect_unix(path)
+    }
+
+    /// Connect the client to a server accepting WebSocket upgrades at a `ws://` url, see
+    /// [ServerBuilder::websocket](crate::server::ServerBuilder::websocket).
+    /// Use [ClientBuilder::connect_websocket] to configure the handshake and timeouts.
+    #[cfg(feature = "websocket")]
+    pub fn connect_websocket(url: &str) -> Result<Self, Error> {
+        ClientBuilder::new().connect_websocket(url)
+    }
+
+    /// Get the settings agreed with the server when connecting.
+    pub fn negotiated(&self) -> &Hello {
+        &self.negotiated
     }
 
     /// Send a [Packet] to the server.
-    pub fn send(&mut self, packet: Packet) -> Result<usize, std::io::Error> {
-        self.stream.write(packet.encode().as_slice())
+    pub fn send(&mut self, packet: Packet) -> Result<usize, Error> {
+        self.connection.send(&packet)
     }
 
     /// Listen to a [Packet] from the server.
-    pub fn read(&mut self) -> Result<Packet, ReadingError> {
-        let mut data = [0 as u8; 50];
-
-        match self.stream.read(&mut data) {
-            Ok(_) => {
-                if let Ok(packet) = Packet::decode(data.to_vec()) {
-                    Ok(packet)
-                } else {
-                    Err(ReadingError::Decode)
-                }
-            }
-            Err(_) => Err(ReadingError::Reading),
+    /// Returns [Error::Disconnected] once the server closed the connection.
+    ///
+    /// Returns [Error::Timeout] if the read timeout expires, or if the heartbeat timeout expires,
+    /// in which case the connection is closed.
+    pub fn read(&mut self) -> Result<Packet, Error> {
+        let result = self.connection.read();
+        self.close_if_idle();
+
+        result
+    }
+
+    /// Listen to a [Packet] from the server like [Client::read], returning [Error::Timeout] after
+    /// `timeout` instead of the read timeout.
+    pub fn read_within(&mut self, timeout: Duration) -> Result<Packet, Error> {
+        let result = self.connection.read_within(timeout);
+        self.close_if_idle();
+
+        result
+    }
+
+    fn close_if_idle(&self) {
+        if self.connection.is_idle() {
+            let _ = self.disconnect();
+            self.log(
+                LogLevel::WARN,
+                "Server stopped answering, closing the connection.",
+            );
         }
     }
 
     /// Close the connection with the client.
-    pub fn disconnect(&self) -> Result<(), std::io::Error> {
-        self.stream.shutdown(Shutdown::Both)?;
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.connection.shutdown(Shutdown::Both)?;
 
         Ok(())
     }
+
+    /// Log a message from the physical client, dropped if no `logger` is set.
+    pub fn log(&self, level: LogLevel, message: &str) {
+        log(&self.log_handler, level, message);
+    }
+
+    /// Split the client into a [PacketReader] and a [PacketWriter], so one thread can wait for
+    /// packets while others send them.
+    pub fn split(self) -> Result<(PacketReader, PacketWriter), Error> {
+        let log_handler = self.log_handler;
+
+        split::split(
+            self.connection,
+            Box::new(move || {
+                log(
+                    &log_handler,
+                    LogLevel::WARN,
+                    "Server stopped answering, closing the connection.",
+                )
+            }),
+            #[cfg(feature = "serde")]
+            self.codec,
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Client {
+    /// Sets the codec used by [Client::send_message] and [Client::read_message].
+    pub fn set_message_codec(&mut self, codec: MessageCodec) {
+        self.codec = codec;
+    }
+
+    /// Serialize a message and send it to the server.
+    pub fn send_message<T: Serialize>(&mut self, message: &T) -> Result<usize, MessageError> {
+        self.send(self.codec.to_packet(message)?)
+            .map_err(MessageError::Connection)
+    }
+
+    /// Listen to a message from the server and deserialize it.
+    pub fn read_message<T: DeserializeOwned>(&mut self) -> Result<T, MessageError> {
+        let packet = self.read().map_err(MessageError::Connection)?;
+        self.codec.from_packet(packet)
+    }
+}
+
+/// Client builder object.
+/// Can be used to connect [Client] objects in a convenient and flexible way.
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use bitsock::client::ClientBuilder;
+///
+/// let client = ClientBuilder::new()
+///     .address("127.0.0.1")
+///     .port(4444)
+///     .connect_timeout(Duration::from_secs(2))
+///     .retries(5)
+///     .nodelay(true)
+///     .heartbeat(Duration::from_secs(10), Duration::from_secs(30))
+///     .connect()
+///     .unwrap();
+/// ```
+pub struct ClientBuilder {
+    address: String,
+    port: u16,
+    local_address: Option<(String, u16)>,
+    connect_timeout: Option<Duration>,
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    nodelay: bool,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    options: ConnectionOptions,
+    #[cfg(feature = "rustls")]
+    tls: Option<Arc<ClientConfig>>,
+    hello: Hello,
+    hello_timeout: Duration,
+    auth_handler: Option<Arc<ClientAuthHandler>>,
+    log_handler: Option<Arc<LogHandler>>,
+}
+
+impl ClientBuilder {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: 4444,
+            local_address: None,
+            connect_timeout: None,
+            retries: 0,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            nodelay: false,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            options: ConnectionOptions::default(),
+            #[cfg(feature = "rustls")]
+            tls: None,
+            hello: Hello::default(),
+            hello_timeout: HELLO_TIMEOUT,
+            auth_handler: None,
+            log_handler: None,
+        }
+    }
+
+    /// Sets the server address.
+    pub fn address(self, address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ..self
+        }
+    }
+
+    /// Sets the server port.
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Binds the client to a local address and port before connecting, port 0 picks any free port.
+    pub fn local_address(self, address: &str, port: u16) -> Self {
+        Self {
+            local_address: Some((address.to_string(), port)),
+            ..self
+        }
+    }
+
+    /// Sets how long a connection attempt can take before it fails, unlimited by default.
+    pub fn connect_timeout(self, timeout: Duration) -> Self {
+        Self {
+            connect_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets how many times a failed connection attempt is retried, 0 by default.
+    pub fn retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// Sets the delay before the first retry and the maximum delay between retries,
+    /// 100 milliseconds and 5 seconds by default. The delay doubles after every failed retry
+    /// and a random jitter of up to half of it is applied.
+    pub fn backoff(self, initial: Duration, max: Duration) -> Self {
+        Self {
+            backoff: initial,
+            max_backoff: max,
+            ..self
+        }
+    }
+
+    /// Sets `TCP_NODELAY`, sending small packets right away instead of batching them.
+    pub fn nodelay(self, nodelay: bool) -> Self {
+        Self { nodelay, ..self }
+    }
+
+    /// Sets the size of the socket send buffer.
+    pub fn send_buffer_size(self, size: usize) -> Self {
+        Self {
+            send_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Sets the size of the socket receive buffer.
+    pub fn recv_buffer_size(self, size: usize) -> Self {
+        Self {
+            recv_buffer_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Sets how long [Client::read] waits for a packet before returning [Error::Timeout].
+    /// A frame still arriving when it expires closes the connection with [Error::ConnectionLost].
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                read_timeout: Some(timeout),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Sets how long [Client::send] waits for the packet to be written before returning [Error::Timeout].
+    pub fn write_timeout(self, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                write_timeout: Some(timeout),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Enables TCP keepalive, probing the server after `time` without traffic.
+    pub fn keepalive(self, time: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                keepalive: Some(time),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Enables the heartbeat: while [Client::read] waits, a ping is sent to the server after
+    /// `interval` of silence, and the connection is closed with [Error::Timeout] after `timeout`
+    /// of silence. Pings from the server are always answered by [Client::read].
+    pub fn heartbeat(self, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                heartbeat: Some(Heartbeat { interval, timeout }),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Connect over TLS using the given configuration, the address is also the name the server
+    /// certificate is verified against.
+    #[cfg(feature = "rustls")]
+    pub fn tls(self, config: Arc<ClientConfig>) -> Self {
+        Self {
+            tls: Some(config),
+            ..self
+        }
+    }
+
+    /// Sets the application protocol announced in the [Hello], the server must speak the same
+    /// name and version.
+    pub fn protocol(self, name: &str, version: u32) -> Self {
+        Self {
+            hello: Hello {
+                protocol: name.to_string(),
+                protocol_version: version,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets the capabilities announced in the [Hello].
+    pub fn capabilities(self, capabilities: Capabilities) -> Self {
+        Self {
+            hello: Hello {
+                capabilities,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets how long the server has to answer the [Hello] before connecting fails with
+    /// [Error::Timeout], [HELLO_TIMEOUT] by default.
+    pub fn hello_timeout(self, hello_timeout: Duration) -> Self {
+        Self {
+            hello_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the client `auth handler`, running the client side of the handshake expected by
+    /// [ServerBuilder::auth_handler](crate::server::ServerBuilder::auth_handler) on every
+    /// connection. Connecting fails with [Error::AuthRejected] if the server rejects the client.
+    /// Without one, connecting to a server that requires authentication fails with
+    /// [Error::Handshake].
+    pub fn auth_handler(self, handler: ClientAuthHandler) -> Self {
+        Self {
+            auth_handler: Some(Arc::new(handler)),
+            ..self
+        }
+    }
+
+    /// Sets the client `logger`, called with [LogStage::CLIENT]. Nothing is logged without one.
+    pub fn log_handler(self, handler: LogHandler) -> Self {
+        Self {
+            log_handler: Some(Arc::new(handler)),
+            ..self
+        }
+    }
+
+    /// Connect the client to the server, retrying as configured.
+    pub fn connect(self) -> Result<Client, Error> {
+        self.open_client(&|| false)
+    }
+
+    /// Connect the client to a server listening on a Unix domain socket at `path`, see
+    /// [ServerBuilder::unix_path](crate::server::ServerBuilder::unix_path). The address, port,
+    /// retries and TCP options are ignored, and TLS is not supported.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(self, path: P) -> Result<Client, Error> {
+        let address = path.as_ref().display().to_string();
+        let stream = UnixStream::connect(path).map_err(|source| Error::Connect {
+            address: address.clone(),
+            source,
+        })?;
+
+        self.handshake(Stream::Unix(stream), &address)
+    }
+
+    /// Connect the client to a server accepting WebSocket upgrades at a `ws://` url, see
+    /// [ServerBuilder::websocket](crate::server::ServerBuilder::websocket). The address, port,
+    /// retries and TCP options are ignored, and TLS is not supported.
+    #[cfg(feature = "websocket")]
+    pub fn connect_websocket(self, url: &str) -> Result<Client, Error> {
+        let stream = Stream::WebSocket(Box::new(websocket::connect(url)?));
+
+        self.handshake(stream, url)
+    }
+
+    /// Internal function, connects a new [Client] without consuming the builder.
+    /// Retries stop early with [Error::Disconnected] once `cancelled` returns true.
+    pub(crate) fn open_client(&self, cancelled: &dyn Fn() -> bool) -> Result<Client, Error> {
+        #[cfg(feature = "rustls")]
+        let name =
+            match &self.tls {
+                Some(_) => Some(ServerName::try_from(self.address.clone()).map_err(|e| {
+                    Error::Connect {
+                        address: self.address.clone(),
+                        source: std::io::Error::new(ErrorKind::InvalidInput, e),
+                    }
+                })?),
+                None => None,
+            };
+
+        let address = format!("{}:{}", self.address, self.port);
+        let stream = self.connect_with_retries(&address, cancelled)?;
+
+        #[cfg(feature = "rustls")]
+        let stream = match (&self.tls, name) {
+            (Some(config), Some(name)) => {
+                let connection = ClientConnection::new(config.clone(), name).map_err(Error::Tls)?;
+
+                // Complete the handshake now, so certificate errors are reported by the connection.
+                let mut stream = StreamOwned::new(connection, stream);
+                while stream.conn.is_handshaking() {
+                    stream
+                        .conn
+                        .complete_io(&mut stream.sock)
+                        .map_err(|source| Error::Connect {
+                            address: address.clone(),
+                            source,
+                        })?;
+                }
+
+                Stream::TlsClient(Box::new(stream))
+            }
+            _ => Stream::Tcp(stream),
+        };
+        #[cfg(not(feature = "rustls"))]
+        let stream = Stream::Tcp(stream);
+
+        self.handshake(stream, &address)
+    }
+
+    /// Internal function, exchanges the hellos and authenticates over a new stream, every
+    /// transport goes through it.
+    fn handshake(&self, stream: Stream, address: &str) -> Result<Client, Error> {
+        #[cfg(feature = "rustls")]
+        if self.tls.is_some() && !matches!(stream, Stream::TlsClient(_)) {
+            return Err(Error::Connect {
+                address: address.to_string(),
+                source: std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "TLS is not supported on this transport",
+                ),
+            });
+        }
+
+        let mut connection = Connection::new(stream, self.options)?;
+        let mut client = Client {
+            negotiated: hello::connect(&mut connection, &self.hello, self.hello_timeout)?,
+            connection,
+            log_handler: self.log_handler.clone(),
+            #[cfg(feature = "serde")]
+            codec: MessageCodec::default(),
+        };
+
+        match &self.auth_handler {
+            Some(handler) => {
+                auth::authenticate(&mut client, handler)?;
+                self.log(LogLevel::INFO, &format!("Authenticated with {}.", address));
+            }
+            None if client.negotiated.auth_required => {
+                let _ = client.disconnect();
+                return Err(Error::Handshake(
+                    "the server requires authentication".to_string(),
+                ));
+            }
+            None => {}
+        }
+
+        Ok(client)
+    }
+
+    /// Internal function, opens the TCP connection retrying with an exponential backoff.
+    fn connect_with_retries(
+        &self,
+        address: &str,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Result<TcpStream, Error> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+
+        loop {
+            self.log(LogLevel::INFO, &format!("Connecting to {}", address));
+
+            match self.open(address) {
+                Ok(stream) => {
+                    self.log(LogLevel::INFO, &format!("Connected to {}.", address));
+                    return Ok(stream);
+                }
+                Err(source) if attempt < self.retries => {
+                    attempt += 1;
+                    let delay = jitter(backoff);
+                    self.log(
+                        LogLevel::WARN,
+                        &format!(
+                            "Failed to connect to {}: {}, retrying in {:?} ({}/{}).",
+                            address, source, delay, attempt, self.retries
+                        ),
+                    );
+
+                    thread::sleep(delay);
+                    backoff = (backoff * 2).min(self.max_backoff);
+
+                    if cancelled() {
+                        return Err(Error::Disconnected);
+                    }
+                }
+                Err(source) => {
+                    self.log(
+                        LogLevel::ERROR,
+                        &format!("Failed to connect to {}: {}", address, source),
+                    );
+                    return Err(Error::Connect {
+                        address: address.to_string(),
+                        source,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Internal function, makes one connection attempt to every address the server resolves to.
+    fn open(&self, address: &str) -> Result<TcpStream, std::io::Error> {
+        let mut last_error = None;
+
+        for address in address.to_socket_addrs()? {
+            match self.open_socket(address) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidInput, "address resolved to nothing")
+        }))
+    }
+
+    /// Internal function, applies the socket options and connects to a single address.
+    fn open_socket(&self, address: SocketAddr) -> Result<TcpStream, std::io::Error> {
+        let socket = Socket::new(
+            Domain::for_address(address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+
+        if let Some((local, port)) = &self.local_address {
+            let local = (local.as_str(), *port)
+                .to_socket_addrs()?
+                .find(|local| local.is_ipv4() == address.is_ipv4())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "local address does not match the server address family",
+                    )
+                })?;
+            socket.bind(&local.into())?;
+        }
+
+        socket.set_tcp_nodelay(self.nodelay)?;
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        match self.connect_timeout {
+            Some(timeout) => socket.connect_timeout(&address.into(), timeout)?,
+            None => socket.connect(&address.into())?,
+        }
+
+        Ok(socket.into())
+    }
+
+    /// Internal function, logs a message while connecting.
+    fn log(&self, level: LogLevel, message: &str) {
+        log(&self.log_handler, level, message);
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Passes a log to the client `logger`, clients are silent without one.
+fn log(handler: &Option<Arc<LogHandler>>, level: LogLevel, message: &str) {
+    if let Some(handler) = handler {
+        handler(LogStage::CLIENT, level, message);
+    }
+}
+
+/// Returns the delay with a random jitter of up to half of it removed.
+fn jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    let random = RandomState::new().hash_one(delay) % (half.as_nanos() as u64 + 1);
+
+    delay - Duration::from_nanos(random)
 }
@@ -0,0 +1,257 @@
+use std::{fmt, io::ErrorKind};
+
+#[cfg(feature = "serde")]
+use crate::message::MessageError;
+use crate::{protocol::ProtocolError, rpc::RpcError};
+
+/// Error returned by every fallible operation of bitsock.
+///
+/// Variants wrapping another error print it and expose its source, variants adding context only
+/// print the context and return the inner error from [source](std::error::Error::source).
+///
+/// ```
+/// // Reacting to a failed read
+/// use bitsock::{client::Client, Error};
+///
+/// fn read(client: &mut Client) {
+///     match client.read() {
+///         Ok(packet) => println!("Received: {:?}", packet),
+///         Err(Error::Disconnected) => println!("The server closed the connection."),
+///         Err(Error::Timeout) => println!("The server is not answering."),
+///         Err(e) => eprintln!("Read failed: {}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// The peer closed the connection.
+    Disconnected,
+
+    /// The connection was reset or aborted, broke while writing, or was closed because a read timed
+    /// out in the middle of a frame. The socket error is the source.
+    ConnectionLost(std::io::Error),
+
+    /// A read or a write did not complete in time.
+    Timeout,
+
+    /// A client stopped answering the heartbeat and the server dropped it, passed to the server
+    /// `error handler`.
+    Idle { address: String },
+
+    /// A frame announces a payload bigger than the maximum frame size.
+    FrameTooLarge { size: usize, max: usize },
+
+    /// A packet cannot be decoded. `tag` is [None] when the failure happened before the tag was
+    /// read, `offset` is the position of the offending byte in the encoded packet.
+    Decode {
+        tag: Option<u8>,
+        offset: usize,
+        error: PacketDecodeError,
+    },
+
+    /// The server cannot bind its listener to the address.
+    Bind {
+        address: String,
+        source: std::io::Error,
+    },
+
+    /// The client cannot connect to the address.
+    Connect {
+        address: String,
+        source: std::io::Error,
+    },
+
+    /// A TLS configuration or connection cannot be set up.
+    #[cfg(feature = "rustls")]
+    Tls(rustls::Error),
+
+    /// A WebSocket handshake or message failed.
+    #[cfg(feature = "websocket")]
+    WebSocket(tungstenite::Error),
+
+    /// The hello exchange failed: the peers speak incompatible protocols or the server refused the
+    /// connection, with the reason.
+    Handshake(String),
+
+    /// The authentication handshake failed, with the reason given by the server or by the
+    /// client `auth handler`.
+    AuthRejected(String),
+
+    /// A typed message cannot be encoded or decoded, or the packet is not a message.
+    #[cfg(feature = "serde")]
+    Message(Box<MessageError>),
+
+    /// A remote procedure call failed on the server or names a method it does not know.
+    Rpc(Box<RpcError>),
+
+    /// A packet is not a message of the application [Protocol](crate::protocol::Protocol).
+    Protocol(ProtocolError),
+
+    /// A client handler panicked while the server was running.
+    HandlerPanicked,
+
+    /// The outbound queue of a [ReconnectingClient](crate::reconnect::ReconnectingClient)
+    /// already holds `limit` packets.
+    QueueFull { limit: usize },
+
+    /// Any other I/O error.
+    Io(std::io::Error),
+}
+
+/// Reason why a packet cannot be decoded, carried by [Error::Decode].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketDecodeError {
+    /// There are no bytes to decode.
+    Empty,
+
+    /// The bytes come from a peer using the unversioned format that predates
+    /// [WIRE_VERSION](crate::WIRE_VERSION).
+    LegacyFormat,
+
+    /// The bytes use a wire format version this crate does not understand.
+    UnsupportedVersion(u8),
+
+    /// The tag does not match any packet type.
+    UnknownTag(u8),
+
+    /// The body length does not match the layout of the tag.
+    InvalidLength(usize),
+
+    /// The body of a [Packet::String](crate::Packet::String) is not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "connection closed by the peer"),
+            Error::ConnectionLost(_) => write!(f, "connection lost"),
+            Error::Timeout => write!(f, "operation timed out"),
+            Error::Idle { address } => write!(f, "client {} stopped answering", address),
+            Error::FrameTooLarge { size, max } => {
+                write!(f, "frame of {} bytes exceeds the maximum of {}", size, max)
+            }
+            Error::Decode { tag, offset, .. } => match tag {
+                Some(tag) => write!(
+                    f,
+                    "failed to decode packet with tag {} at offset {}",
+                    tag, offset
+                ),
+                None => write!(f, "failed to decode packet at offset {}", offset),
+            },
+            Error::Bind { address, .. } => {
+                write!(f, "failed to bind listener to address {}", address)
+            }
+            Error::Connect { address, .. } => write!(f, "failed to connect to {}", address),
+            #[cfg(feature = "rustls")]
+            Error::Tls(e) => write!(f, "{}", e),
+            #[cfg(feature = "websocket")]
+            Error::WebSocket(e) => write!(f, "{}", e),
+            Error::Handshake(reason) => write!(f, "handshake failed: {}", reason),
+            Error::AuthRejected(reason) => write!(f, "authentication rejected: {}", reason),
+            #[cfg(feature = "serde")]
+            Error::Message(e) => write!(f, "{}", e),
+            Error::Rpc(e) => write!(f, "{}", e),
+            Error::Protocol(e) => write!(f, "{}", e),
+            Error::HandlerPanicked => write!(f, "a client handler panicked"),
+            Error::QueueFull { limit } => {
+                write!(f, "outbound queue is full ({} packets)", limit)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Decode { error, .. } => Some(error),
+            Error::ConnectionLost(source)
+            | Error::Bind { source, .. }
+            | Error::Connect { source, .. } => Some(source),
+            #[cfg(feature = "rustls")]
+            Error::Tls(e) => e.source(),
+            #[cfg(feature = "websocket")]
+            Error::WebSocket(e) => e.source(),
+            #[cfg(feature = "serde")]
+            Error::Message(e) => e.source(),
+            Error::Rpc(e) => e.source(),
+            Error::Protocol(e) => e.source(),
+            Error::Io(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::UnexpectedEof => Error::Disconnected,
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+                Error::ConnectionLost(error)
+            }
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(error),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MessageError> for Error {
+    fn from(error: MessageError) -> Self {
+        match error {
+            MessageError::Connection(e) => e,
+            e => Error::Message(Box::new(e)),
+        }
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(error: RpcError) -> Self {
+        match error {
+            RpcError::Connection(e) => e,
+            RpcError::Timeout => Error::Timeout,
+            e => Error::Rpc(Box::new(e)),
+        }
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(error: ProtocolError) -> Self {
+        Error::Protocol(error)
+    }
+}
+
+/// Returns an error followed by each of its sources, as printed when no `error handler` is set.
+pub(crate) fn report(error: &dyn std::error::Error) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        report.push_str(": ");
+        report.push_str(&e.to_string());
+        source = e.source();
+    }
+
+    report
+}
+
+impl fmt::Display for PacketDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketDecodeError::Empty => write!(f, "no bytes to decode"),
+            PacketDecodeError::LegacyFormat => {
+                write!(f, "packet uses the unversioned legacy format")
+            }
+            PacketDecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported wire format version {}", version)
+            }
+            PacketDecodeError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            PacketDecodeError::InvalidLength(length) => {
+                write!(f, "invalid body length {}", length)
+            }
+            PacketDecodeError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for PacketDecodeError {}
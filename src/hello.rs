@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+use crate::{
+    stream::Connection, Error, Packet, HELLO_ID, HELLO_REJECTED_ID, MAX_FRAME_SIZE, WIRE_VERSION,
+};
+
+/// How long a peer can take to send its hello before the connection is dropped by default, see
+/// [ServerBuilder::hello_timeout](crate::server::ServerBuilder::hello_timeout) and
+/// [ClientBuilder::hello_timeout](crate::client::ClientBuilder::hello_timeout).
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size in bytes of a hello body before the protocol name.
+const HEADER_SIZE: usize = 10;
+
+const ENCRYPTION: u8 = 1;
+const AUTH_REQUIRED: u8 = 1 << 1;
+
+/// Features a peer supports, announced in its [Hello]. Only the ones both peers support are agreed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The peer encrypts the connection, set automatically on TLS connections.
+    pub encryption: bool,
+    /// Largest frame payload the peer accepts, the agreed size is the smaller of the two.
+    pub max_frame_size: usize,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            encryption: false,
+            max_frame_size: MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Settings announced by a peer when the connection opens, and the settings both peers agreed on.
+///
+/// Every [Client](crate::client::Client) sends its hello right after connecting, the
+/// [Server](crate::server::Server) answers with its own or refuses the connection. Peers agree
+/// when they use the same wire format and the same application protocol name and version.
+///
+/// A hello is a [Packet::Identified] whose body is the wire version, a flags byte (bit 0 for
+/// encryption, bit 1 when authentication is required), the maximum frame size and the protocol
+/// version as little-endian [u32]s, then the protocol name in UTF-8. WebSocket peers such as browsers must
+/// send it as their first message, see [Hello::to_packet].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub wire_version: u8,
+    pub protocol: String,
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+    /// The server authenticates its clients before handing them to the `client handler`, set
+    /// automatically by servers with an `auth handler`.
+    pub auth_required: bool,
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            wire_version: WIRE_VERSION,
+            protocol: String::new(),
+            protocol_version: 0,
+            capabilities: Capabilities::default(),
+            auth_required: false,
+        }
+    }
+}
+
+impl Hello {
+    /// Returns the settings agreed with a peer that sent `peer`, or why the peers cannot talk.
+    pub fn negotiate(&self, peer: &Hello) -> Result<Hello, String> {
+        if peer.wire_version != self.wire_version {
+            return Err(format!(
+                "wire version {} is not supported, expected {}",
+                peer.wire_version, self.wire_version
+            ));
+        }
+        if peer.protocol != self.protocol {
+            return Err(format!(
+                "protocol {:?} is not supported, expected {:?}",
+                peer.protocol, self.protocol
+            ));
+        }
+        if peer.protocol_version != self.protocol_version {
+            return Err(format!(
+                "protocol version {} is not supported, expected {}",
+                peer.protocol_version, self.protocol_version
+            ));
+        }
+
+        Ok(Hello {
+            capabilities: Capabilities {
+                encryption: self.capabilities.encryption && peer.capabilities.encryption,
+                max_frame_size: self
+                    .capabilities
+                    .max_frame_size
+                    .min(peer.capabilities.max_frame_size),
+            },
+            auth_required: self.auth_required || peer.auth_required,
+            ..self.clone()
+        })
+    }
+
+    /// Encode the hello into the packet sent to the peer.
+    pub fn to_packet(&self) -> Packet {
+        let mut flags = 0;
+        if self.capabilities.encryption {
+            flags |= ENCRYPTION;
+        }
+        if self.auth_required {
+            flags |= AUTH_REQUIRED;
+        }
+        let max_frame_size = self.capabilities.max_frame_size.min(u32::MAX as usize) as u32;
+
+        let mut body = Vec::with_capacity(HEADER_SIZE + self.protocol.len());
+        body.push(self.wire_version);
+        body.push(flags);
+        body.extend_from_slice(&max_frame_size.to_le_bytes());
+        body.extend_from_slice(&self.protocol_version.to_le_bytes());
+        body.extend_from_slice(self.protocol.as_bytes());
+
+        Packet::Identified(HELLO_ID, body)
+    }
+
+    /// Decode a hello received from the peer, returns [None] if the packet is not a valid hello.
+    pub fn from_packet(packet: &Packet) -> Option<Self> {
+        let body = match packet {
+            Packet::Identified(HELLO_ID, body) if body.len() >= HEADER_SIZE => body,
+            _ => return None,
+        };
+
+        Some(Self {
+            wire_version: body[0],
+            protocol: String::from_utf8(body[HEADER_SIZE..].to_vec()).ok()?,
+            protocol_version: u32::from_le_bytes(body[6..HEADER_SIZE].try_into().unwrap()),
+            capabilities: Capabilities {
+                encryption: body[1] & ENCRYPTION != 0,
+                max_frame_size: u32::from_le_bytes(body[2..6].try_into().unwrap()) as usize,
+            },
+            auth_required: body[1] & AUTH_REQUIRED != 0,
+        })
+    }
+
+    /// Returns the hello announced on a connection, encryption is announced on TLS connections.
+    fn on(&self, connection: &Connection) -> Hello {
+        let mut hello = self.clone();
+        hello.capabilities.encryption |= connection.is_encrypted();
+
+        hello
+    }
+}
+
+/// Builds the packet refusing the connection of a client.
+pub(crate) fn rejection(reason: &str) -> Packet {
+    Packet::Identified(HELLO_REJECTED_ID, reason.as_bytes().to_vec())
+}
+
+/// Client side of the exchange: sends the hello, then waits for the one of the server for at most
+/// `timeout`.
+pub(crate) fn connect(
+    connection: &mut Connection,
+    hello: &Hello,
+    timeout: Duration,
+) -> Result<Hello, Error> {
+    let hello = hello.on(connection);
+    connection.send(&hello.to_packet())?;
+
+    let agreed = check_answer(&hello, &connection.read_within(timeout)?)?;
+    connection.set_max_frame_size(agreed.capabilities.max_frame_size);
+
+    Ok(agreed)
+}
+
+/// Returns the settings agreed with a server that answered `packet` to `hello`.
+pub(crate) fn check_answer(hello: &Hello, packet: &Packet) -> Result<Hello, Error> {
+    match (packet, Hello::from_packet(packet)) {
+        (_, Some(peer)) => hello.negotiate(&peer).map_err(Error::Handshake),
+        (Packet::Identified(HELLO_REJECTED_ID, reason), None) => Err(Error::Handshake(
+            String::from_utf8_lossy(reason).into_owned(),
+        )),
+        _ => Err(Error::Handshake(
+            "expected the hello of the server".to_string(),
+        )),
+    }
+}
+
+/// Server side of the exchange: waits for the hello of the client for at most `timeout`, then
+/// answers with its own or refuses the connection.
+pub(crate) fn accept(
+    connection: &mut Connection,
+    hello: &Hello,
+    timeout: Duration,
+) -> Result<Hello, Error> {
+    let hello = hello.on(connection);
+
+    let packet = connection.read_within(timeout)?;
+
+    match check_hello(&hello, &packet) {
+        Ok(agreed) => {
+            connection.send(&hello.to_packet())?;
+            connection.set_max_frame_size(agreed.capabilities.max_frame_size);
+
+            Ok(agreed)
+        }
+        Err(reason) => {
+            let _ = connection.send(&rejection(&reason));
+
+            Err(Error::Handshake(reason))
+        }
+    }
+}
+
+/// Returns the settings agreed with a client that sent `packet`, or why it is refused.
+pub(crate) fn check_hello(hello: &Hello, packet: &Packet) -> Result<Hello, String> {
+    Hello::from_packet(packet)
+        .ok_or_else(|| "expected a hello".to_string())
+        .and_then(|peer| hello.negotiate(&peer))
+}
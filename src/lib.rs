@@ -1,37 +1,114 @@
-use std::io::Cursor;
+use std::io::{Cursor, Read, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 #[cfg(test)]
 mod tests;
 
+// Lets the code generated by `bitsock-derive` refer to `::bitsock` inside this crate too.
+extern crate self as bitsock;
+
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod auth;
 pub mod client;
+mod error;
+pub mod extensions;
+pub mod hello;
+#[cfg(feature = "serde")]
+pub mod message;
+pub mod protocol;
+pub mod reconnect;
+pub mod reliable;
+pub mod rpc;
 pub mod server;
+pub mod split;
+mod stream;
+pub mod udp;
+#[cfg(feature = "websocket")]
+mod websocket;
 
-#[derive(Debug)]
-pub enum ReadingError {
-    /// Error returned when the server fails to read data from the client.
-    Reading,
+#[cfg(feature = "derive")]
+pub use bitsock_derive::BitsockProtocol;
+pub use error::{Error, PacketDecodeError};
+#[cfg(feature = "rustls")]
+pub use rustls;
 
-    /// Error returned when the readed packet fails to be decoded
-    Decode,
-}
+/// Version of the wire format written by [Packet::encode] and [Packet::frame].
+pub const WIRE_VERSION: u8 = 1;
 
-#[derive(Debug)]
-pub enum ConnectionError {
-    /// Error returned when a client fail to connect.
-    Client(String),
-}
+/// Bit set in every version byte. Packets written before the wire format was versioned start
+/// with their tag (1–13) instead, so a clear bit identifies an older peer.
+pub const VERSION_MARKER: u8 = 0x80;
 
-/// Error returned when a packet cannot be decoded from [Packet::decode].
-#[derive(Debug)]
-pub struct PacketDecodeError;
+/// Size in bytes of the header written before every frame: the version byte and a [u32] length.
+pub const FRAME_HEADER_SIZE: usize = 1 + std::mem::size_of::<u32>();
+
+/// Maximum size in bytes of a frame payload, larger frames are rejected by [Packet::read_from].
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// First [Packet::Identified] id reserved for the packets sent by bitsock itself,
+/// applications should only use lower ids.
+pub const RESERVED_ID_START: u32 = 0xFFFF_0000;
+
+/// [Packet::Identified] id of the heartbeat sent to check that the peer is still there.
+pub(crate) const PING_ID: u32 = RESERVED_ID_START + 0x10;
+
+/// [Packet::Identified] id of the answer to a [PING_ID] heartbeat.
+pub(crate) const PONG_ID: u32 = RESERVED_ID_START + 0x11;
+
+/// [Packet::Identified] id of a remote procedure call, see [rpc].
+pub(crate) const RPC_REQUEST_ID: u32 = RESERVED_ID_START + 0x20;
+
+/// [Packet::Identified] id of the result of a remote procedure call.
+pub(crate) const RPC_RESPONSE_ID: u32 = RESERVED_ID_START + 0x21;
+
+/// [Packet::Identified] id of the error of a remote procedure call.
+pub(crate) const RPC_ERROR_ID: u32 = RESERVED_ID_START + 0x22;
+
+/// [Packet::Identified] id of the packets exchanged during authentication, see [auth].
+pub const AUTH_ID: u32 = RESERVED_ID_START + 0x30;
+
+/// [Packet::Identified] id of the outcome of authentication sent by the server.
+pub(crate) const AUTH_RESULT_ID: u32 = RESERVED_ID_START + 0x31;
+
+/// [Packet::Identified] id of the hello sent by both peers when the connection opens, see [hello].
+pub const HELLO_ID: u32 = RESERVED_ID_START + 0x40;
+
+/// [Packet::Identified] id of the reason why the server refused the hello of a client.
+pub(crate) const HELLO_REJECTED_ID: u32 = RESERVED_ID_START + 0x41;
 
 /// Enum containing all the possible packet types.
-#[derive(Debug)]
+///
+/// # Wire format
+///
+/// Version 1 of the wire format encodes a packet as a version byte (`VERSION_MARKER | 1`), a tag
+/// byte and a body. Every number is little-endian and every body has an exact length:
+///
+/// | Tag | Variant              | Body                                   |
+/// | --- | -------------------- | -------------------------------------- |
+/// | 0   | [Packet::Invalid]    | empty                                  |
+/// | 1   | [Packet::Bytes]      | the bytes                              |
+/// | 2   | [Packet::String]     | the UTF-8 bytes of the string          |
+/// | 3   | [Packet::I8]         | 1 byte                                 |
+/// | 4   | [Packet::I16]        | 2 bytes                                |
+/// | 5   | [Packet::I32]        | 4 bytes                                |
+/// | 6   | [Packet::I64]        | 8 bytes                                |
+/// | 7   | [Packet::F32]        | 4 bytes                                |
+/// | 8   | [Packet::F64]        | 8 bytes                                |
+/// | 9   | [Packet::U8]         | 1 byte                                 |
+/// | 10  | [Packet::U16]        | 2 bytes                                |
+/// | 11  | [Packet::U32]        | 4 bytes                                |
+/// | 12  | [Packet::U64]        | 8 bytes                                |
+/// | 13  | [Packet::Identified] | the [u32] id followed by the bytes     |
+///
+/// On streams every packet is sent as a frame: the version byte, a [u32] with the length of the
+/// tag and body, then the tag and body (see [Packet::frame]).
+#[derive(Debug, Clone, PartialEq)]
 pub enum Packet {
+    /// Packet without data, also used for tags this crate does not produce.
     Invalid,
-    // Packet containing data in form of bytes.
+    /// Packet containing data in form of bytes.
     Bytes(Vec<u8>),
     /// Packet containing a [String].
     String(String),
@@ -55,223 +132,268 @@ pub enum Packet {
     U32(u32),
     /// Packet containing a [u64].
     U64(u64),
-    // Packet containing data in form of bytes with an identifier which can represent what type of data the packet contains.
+    /// Packet containing data in form of bytes with an identifier which can represent what type of data the packet contains.
     Identified(u32, Vec<u8>),
 }
 
 impl Packet {
+    /// Returns the tag identifying the packet type on the wire.
+    pub fn tag(&self) -> u8 {
+        match self {
+            Packet::Invalid => 0,
+            Packet::Bytes(_) => 1,
+            Packet::String(_) => 2,
+            Packet::I8(_) => 3,
+            Packet::I16(_) => 4,
+            Packet::I32(_) => 5,
+            Packet::I64(_) => 6,
+            Packet::F32(_) => 7,
+            Packet::F64(_) => 8,
+            Packet::U8(_) => 9,
+            Packet::U16(_) => 10,
+            Packet::U32(_) => 11,
+            Packet::U64(_) => 12,
+            Packet::Identified(_, _) => 13,
+        }
+    }
+
     /// Encode the packet into bytes.
     pub fn encode(&self) -> Vec<u8> {
-        let mut result = Vec::new();
+        let mut result = vec![VERSION_MARKER | WIRE_VERSION];
+        self.encode_body(&mut result);
 
-        match self {
-            Packet::Bytes(data) => {
-                result.insert(0, 1);
-                for _ in 0..(std::mem::size_of::<u8>() * data.len()) {
-                    result.push(0);
-                }
-                for b in data {
-                    result.push(*b)
-                }
-            }
-            Packet::String(data) => {
-                result.insert(0, 2);
-                for _ in 0..std::mem::size_of::<String>() {
-                    result.push(0);
-                }
-                for b in data.as_bytes() {
-                    result.push(*b);
-                }
-            }
-            Packet::I8(data) => {
-                result.insert(0, 3);
-                for _ in 0..std::mem::size_of::<i8>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_i8(*data);
-            }
-            Packet::I16(data) => {
-                result.insert(0, 4);
-                for _ in 0..std::mem::size_of::<i16>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_i16::<LittleEndian>(*data);
-            }
-            Packet::I32(data) => {
-                result.insert(0, 5);
-                for _ in 0..std::mem::size_of::<i32>() {
-                    result.push(0);
-                }
-                let _ = &result[1..]
-                    .as_mut()
-                    .write_i32::<LittleEndian>(*data)
-                    .unwrap();
-            }
-            Packet::I64(data) => {
-                result.insert(0, 6);
-                for _ in 0..std::mem::size_of::<i64>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_i64::<LittleEndian>(*data);
-            }
-            Packet::F32(data) => {
-                result.insert(0, 7);
-                for _ in 0..std::mem::size_of::<f32>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_f32::<LittleEndian>(*data);
-            }
-            Packet::F64(data) => {
-                result.insert(0, 8);
-                for _ in 0..std::mem::size_of::<f64>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_f64::<LittleEndian>(*data);
-            }
-            Packet::U8(data) => {
-                result.insert(0, 9);
-                for _ in 0..std::mem::size_of::<u8>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_u8(*data);
-            }
-            Packet::U16(data) => {
-                result.insert(0, 10);
-                for _ in 0..std::mem::size_of::<u16>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_u16::<LittleEndian>(*data);
+        result
+    }
+
+    /// Writes the tag and the body of the packet, writing into a [Vec] never fails.
+    fn encode_body(&self, result: &mut Vec<u8>) {
+        result.push(self.tag());
+
+        let _ = match self {
+            Packet::Invalid => Ok(()),
+            Packet::Bytes(data) => result.write_all(data),
+            Packet::String(data) => result.write_all(data.as_bytes()),
+            Packet::I8(data) => result.write_i8(*data),
+            Packet::I16(data) => result.write_i16::<LittleEndian>(*data),
+            Packet::I32(data) => result.write_i32::<LittleEndian>(*data),
+            Packet::I64(data) => result.write_i64::<LittleEndian>(*data),
+            Packet::F32(data) => result.write_f32::<LittleEndian>(*data),
+            Packet::F64(data) => result.write_f64::<LittleEndian>(*data),
+            Packet::U8(data) => result.write_u8(*data),
+            Packet::U16(data) => result.write_u16::<LittleEndian>(*data),
+            Packet::U32(data) => result.write_u32::<LittleEndian>(*data),
+            Packet::U64(data) => result.write_u64::<LittleEndian>(*data),
+            Packet::Identified(id, data) => result
+                .write_u32::<LittleEndian>(*id)
+                .and_then(|_| result.write_all(data)),
+        };
+    }
+
+    /// Returns a [Packet] from a [Vec] of bytes.
+    pub fn decode(bytes: Vec<u8>) -> Result<Self, Error> {
+        Self::decode_slice(&bytes)
+    }
+
+    /// Returns a [Packet] from a slice of bytes, like [Packet::decode] without taking ownership.
+    pub fn decode_slice(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.split_first() {
+            Some((version, body)) => {
+                check_version(*version)?;
+                Self::decode_body(body)
             }
-            Packet::U32(data) => {
-                result.insert(0, 11);
-                for _ in 0..std::mem::size_of::<u32>() {
-                    result.push(0);
-                }
-                let _: &Result<(), std::io::Error> =
-                    &result[1..].as_mut().write_u32::<LittleEndian>(*data);
+            None => Err(decode_error(None, 0, PacketDecodeError::Empty)),
+        }
+    }
+
+    /// Decodes the tag and the body of a packet, the version byte must be already checked.
+    /// Offsets in errors are relative to the packet as written by [Packet::encode].
+    pub(crate) fn decode_body(bytes: &[u8]) -> Result<Self, Error> {
+        let (tag, body) = match bytes.split_first() {
+            Some((tag, body)) => (*tag, body),
+            None => return Err(decode_error(None, 1, PacketDecodeError::Empty)),
+        };
+        let invalid = |offset: usize| {
+            decode_error(
+                Some(tag),
+                2 + offset,
+                PacketDecodeError::InvalidLength(body.len()),
+            )
+        };
+        let short = |_| invalid(body.len());
+        let mut cursor = Cursor::new(body);
+
+        let packet = match tag {
+            0 => Packet::Invalid,
+            1 => return Ok(Packet::Bytes(body.to_vec())),
+            2 => {
+                return String::from_utf8(body.to_vec())
+                    .map(Packet::String)
+                    .map_err(|e| {
+                        decode_error(
+                            Some(tag),
+                            2 + e.utf8_error().valid_up_to(),
+                            PacketDecodeError::InvalidUtf8,
+                        )
+                    })
             }
-            Packet::U64(data) => {
-                result.insert(0, 12);
-                for _ in 0..std::mem::size_of::<u64>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_u64::<LittleEndian>(*data);
+            3 => Packet::I8(cursor.read_i8().map_err(short)?),
+            4 => Packet::I16(cursor.read_i16::<LittleEndian>().map_err(short)?),
+            5 => Packet::I32(cursor.read_i32::<LittleEndian>().map_err(short)?),
+            6 => Packet::I64(cursor.read_i64::<LittleEndian>().map_err(short)?),
+            7 => Packet::F32(cursor.read_f32::<LittleEndian>().map_err(short)?),
+            8 => Packet::F64(cursor.read_f64::<LittleEndian>().map_err(short)?),
+            9 => Packet::U8(cursor.read_u8().map_err(short)?),
+            10 => Packet::U16(cursor.read_u16::<LittleEndian>().map_err(short)?),
+            11 => Packet::U32(cursor.read_u32::<LittleEndian>().map_err(short)?),
+            12 => Packet::U64(cursor.read_u64::<LittleEndian>().map_err(short)?),
+            13 => {
+                let id = cursor.read_u32::<LittleEndian>().map_err(short)?;
+                return Ok(Packet::Identified(
+                    id,
+                    body[std::mem::size_of::<u32>()..].to_vec(),
+                ));
             }
-            Packet::Identified(id, data) => {
-                result.insert(0, 13);
-                for _ in 0..std::mem::size_of::<u32>() {
-                    result.push(0);
-                }
-                let _ = &result[1..].as_mut().write_u32::<LittleEndian>(*id);
-
-                for b in data {
-                    result.push(*b)
-                }
+            _ => {
+                return Err(decode_error(
+                    Some(tag),
+                    1,
+                    PacketDecodeError::UnknownTag(tag),
+                ))
             }
-            Packet::Invalid => (),
+        };
+
+        // Bytes left after a fixed size body.
+        if cursor.position() as usize != body.len() {
+            return Err(invalid(cursor.position() as usize));
         }
 
-        result
+        Ok(packet)
     }
 
-    /// Returns a [Packet] from a [Vec] of bytes.
-    pub fn decode(bytes: Vec<u8>) -> Result<Self, PacketDecodeError> {
-        if let Some(b) = bytes.first() {
-            match b {
-                1 => Ok(Packet::Bytes(bytes[1..].to_vec())),
-                2 => Ok(Packet::String(
-                    if let Ok(s) = String::from_utf8(bytes[1..].to_vec()) {
-                        s
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                3 => Ok(Packet::I8(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_i8() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                4 => Ok(Packet::I16(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_i16::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                5 => Ok(Packet::I32(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_i32::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                6 => Ok(Packet::I64(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_i64::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                7 => Ok(Packet::F32(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_f32::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                8 => Ok(Packet::F64(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_f64::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                9 => Ok(Packet::U8(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_u8() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                10 => Ok(Packet::U16(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_u16::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                11 => Ok(Packet::U32(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_u32::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                12 => Ok(Packet::U64(
-                    if let Ok(n) = Cursor::new(bytes[1..].to_vec()).read_u64::<LittleEndian>() {
-                        n
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                )),
-                13 => Ok(Packet::Identified(
-                    if let Ok(id) = Cursor::new(bytes[1..].to_vec()).read_u32::<LittleEndian>() {
-                        id
-                    } else {
-                        return Err(PacketDecodeError);
-                    },
-                    bytes[std::mem::size_of::<u32>()..].to_vec(),
-                )),
-                _ => Ok(Packet::Invalid),
-            }
-        } else {
-            Err(PacketDecodeError)
+    /// Encode the packet into a frame: the version byte, a little-endian [u32] with the length of
+    /// the rest of the frame, then the tag and the body as written by [Packet::encode].
+    pub fn frame(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        self.encode_body(&mut body);
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + body.len());
+        frame.push(VERSION_MARKER | WIRE_VERSION);
+        let _ = frame.write_u32::<LittleEndian>(body.len() as u32);
+        frame.extend_from_slice(&body);
+
+        frame
+    }
+
+    /// Returns the frame of the packet, failing if it is bigger than [MAX_FRAME_SIZE].
+    pub(crate) fn checked_frame(&self) -> Result<Vec<u8>, Error> {
+        let frame = self.frame();
+        let size = frame.len() - FRAME_HEADER_SIZE;
+        if size > MAX_FRAME_SIZE {
+            return Err(Error::FrameTooLarge {
+                size,
+                max: MAX_FRAME_SIZE,
+            });
+        }
+
+        Ok(frame)
+    }
+
+    /// Write the packet to a stream as a single frame, returning the number of bytes written.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let frame = self.checked_frame()?;
+
+        writer.write_all(&frame)?;
+        writer.flush()?;
+
+        Ok(frame.len())
+    }
+
+    /// Read exactly one frame from a stream and decode it into a [Packet].
+    ///
+    /// The version byte is checked before anything else is read, so a peer speaking an older
+    /// format is rejected with [PacketDecodeError::LegacyFormat] instead of being waited on.
+    ///
+    /// Returns [Error::Disconnected] if the stream ends before the first byte of the frame,
+    /// a stream ending in the middle of a frame is reported as an [Error::Io].
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        Self::read_limited(reader, MAX_FRAME_SIZE)
+    }
+
+    /// Reads one frame like [Packet::read_from], rejecting frames bigger than `max`.
+    pub(crate) fn read_limited<R: Read>(reader: &mut R, max: usize) -> Result<Self, Error> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        if read_first_byte(reader, &mut header[0])? == 0 {
+            return Err(Error::Disconnected);
+        }
+        check_version(header[0])?;
+
+        reader.read_exact(&mut header[1..]).map_err(truncated)?;
+        let length = limited_frame_length(header, max)?;
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).map_err(truncated)?;
+
+        Packet::decode_body(&body)
+    }
+}
+
+/// Reads the first byte of a frame, retrying on interruptions. Returns 0 at the end of the stream.
+fn read_first_byte<R: Read>(reader: &mut R, byte: &mut u8) -> Result<usize, Error> {
+    loop {
+        match reader.read(std::slice::from_mut(byte)) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return Ok(result?),
         }
     }
 }
 
+/// Maps the end of the stream in the middle of a frame to an [Error::Io] instead of [Error::Disconnected].
+pub(crate) fn truncated(error: std::io::Error) -> Error {
+    if error.kind() == std::io::ErrorKind::UnexpectedEof {
+        Error::Io(error)
+    } else {
+        error.into()
+    }
+}
+
+/// Returns the length announced by a frame header, checking the version byte and [MAX_FRAME_SIZE].
+pub fn frame_length(header: [u8; FRAME_HEADER_SIZE]) -> Result<usize, Error> {
+    limited_frame_length(header, MAX_FRAME_SIZE)
+}
+
+/// Returns the length announced by a frame header like [frame_length], checking it against `max`.
+pub(crate) fn limited_frame_length(
+    header: [u8; FRAME_HEADER_SIZE],
+    max: usize,
+) -> Result<usize, Error> {
+    check_version(header[0])?;
+
+    let size = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+
+    if size > max {
+        Err(Error::FrameTooLarge { size, max })
+    } else {
+        Ok(size)
+    }
+}
+
+/// Checks that a version byte was written by a peer speaking [WIRE_VERSION].
+fn check_version(version: u8) -> Result<(), Error> {
+    let error = if version & VERSION_MARKER == 0 {
+        PacketDecodeError::LegacyFormat
+    } else if version & !VERSION_MARKER != WIRE_VERSION {
+        PacketDecodeError::UnsupportedVersion(version & !VERSION_MARKER)
+    } else {
+        return Ok(());
+    };
+
+    Err(decode_error(None, 0, error))
+}
+
+fn decode_error(tag: Option<u8>, offset: usize, error: PacketDecodeError) -> Error {
+    Error::Decode { tag, offset, error }
+}
+
 /// Enum used to specify if the log is generated by a physical client or a physical server.
 pub enum LogStage {
     SERVER,
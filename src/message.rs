@@ -0,0 +1,111 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Error, Packet, RESERVED_ID_START};
+
+/// Error returned when a typed message cannot be sent or read.
+#[derive(Debug)]
+pub enum MessageError {
+    /// Error returned when the message cannot be serialized by the codec.
+    Encode(Box<dyn std::error::Error + Send + Sync>),
+
+    /// Error returned when the received message cannot be deserialized by the codec.
+    Decode(Box<dyn std::error::Error + Send + Sync>),
+
+    /// Error returned when the packet carrying the message fails to be sent or read.
+    Connection(Error),
+
+    /// Error returned when the received packet is not a message written with the same codec.
+    UnexpectedPacket(Packet),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::Encode(_) => write!(f, "failed to encode message"),
+            MessageError::Decode(_) => write!(f, "failed to decode message"),
+            MessageError::Connection(e) => write!(f, "{}", e),
+            MessageError::UnexpectedPacket(packet) => {
+                write!(f, "expected a message, received {:?}", packet)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MessageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MessageError::Encode(e) | MessageError::Decode(e) => Some(e.as_ref()),
+            MessageError::Connection(e) => e.source(),
+            MessageError::UnexpectedPacket(_) => None,
+        }
+    }
+}
+
+/// Codec used to serialize typed messages into the body of a [Packet::Identified].
+/// The id of the packet records the codec, so both peers must use the same one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageCodec {
+    /// Compact binary encoding provided by `bincode`.
+    #[default]
+    Bincode,
+    /// JSON encoding provided by `serde_json`.
+    Json,
+    /// MessagePack encoding provided by `rmp-serde`.
+    MessagePack,
+}
+
+impl MessageCodec {
+    /// Returns the [Packet::Identified] id used for messages written with this codec.
+    pub fn id(&self) -> u32 {
+        match self {
+            MessageCodec::Bincode => RESERVED_ID_START,
+            MessageCodec::Json => RESERVED_ID_START + 1,
+            MessageCodec::MessagePack => RESERVED_ID_START + 2,
+        }
+    }
+
+    /// Serialize a message into bytes.
+    pub fn encode<T: Serialize>(&self, message: &T) -> Result<Vec<u8>, MessageError> {
+        match self {
+            MessageCodec::Bincode => {
+                bincode::serialize(message).map_err(|e| MessageError::Encode(e))
+            }
+            MessageCodec::Json => {
+                serde_json::to_vec(message).map_err(|e| MessageError::Encode(e.into()))
+            }
+            MessageCodec::MessagePack => {
+                rmp_serde::to_vec(message).map_err(|e| MessageError::Encode(e.into()))
+            }
+        }
+    }
+
+    /// Deserialize a message from bytes.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, MessageError> {
+        match self {
+            MessageCodec::Bincode => {
+                bincode::deserialize(bytes).map_err(|e| MessageError::Decode(e))
+            }
+            MessageCodec::Json => {
+                serde_json::from_slice(bytes).map_err(|e| MessageError::Decode(e.into()))
+            }
+            MessageCodec::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|e| MessageError::Decode(e.into()))
+            }
+        }
+    }
+
+    /// Serialize a message into the [Packet] that carries it.
+    pub fn to_packet<T: Serialize>(&self, message: &T) -> Result<Packet, MessageError> {
+        Ok(Packet::Identified(self.id(), self.encode(message)?))
+    }
+
+    /// Deserialize a message from the [Packet] that carries it.
+    pub fn from_packet<T: DeserializeOwned>(&self, packet: Packet) -> Result<T, MessageError> {
+        match packet {
+            Packet::Identified(id, data) if id == self.id() => self.decode(&data),
+            packet => Err(MessageError::UnexpectedPacket(packet)),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::Packet;
+
+/// Error returned when a [Packet] cannot be turned into a [Protocol] message.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// Error returned when the packet is not a [Packet::Identified].
+    NotIdentified(Packet),
+
+    /// Error returned when the id of the packet is not assigned to any message.
+    UnknownId(u32),
+
+    /// Error returned when the body of the packet cannot be decoded into the message with that id.
+    InvalidPayload(u32),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::NotIdentified(packet) => {
+                write!(f, "expected an identified packet, received {:?}", packet)
+            }
+            ProtocolError::UnknownId(id) => write!(f, "unknown message id {}", id),
+            ProtocolError::InvalidPayload(id) => write!(f, "invalid payload for message id {}", id),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// Application protocol mapped onto [Packet::Identified], every message has a stable [u32] id.
+///
+/// Usually implemented with `#[derive(BitsockProtocol)]` (requires the `derive` feature):
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # fn main() {
+/// use bitsock::{protocol::Protocol, BitsockProtocol};
+///
+/// #[derive(BitsockProtocol, Debug, PartialEq)]
+/// enum Chat {
+///     Join(String),        // id 0
+///     Leave,               // id 1
+///     #[bitsock(id = 10)]
+///     Message(String),     // id 10
+///     Typing(bool),        // id 11
+/// }
+///
+/// let message = Chat::Message("hi".to_string());
+/// assert_eq!(message.id(), 10);
+/// assert_eq!(Chat::Typing(true).id(), 11);
+/// assert_eq!(Chat::from_packet(message.to_packet()).unwrap(), message);
+/// # }
+/// # #[cfg(not(feature = "derive"))]
+/// # fn main() {}
+/// ```
+/// Variants without an explicit id take the id of the previous variant plus one, starting from 0.
+/// Variants can either carry no data or a single [Payload].
+pub trait Protocol: Sized {
+    /// Returns the id of the message.
+    fn id(&self) -> u32;
+
+    /// Encode the message into a [Packet::Identified].
+    fn to_packet(&self) -> Packet;
+
+    /// Decode a message from a [Packet::Identified].
+    fn from_packet(packet: Packet) -> Result<Self, ProtocolError>;
+}
+
+/// Data that can be carried by a [Protocol] message, as the body of a [Packet::Identified].
+pub trait Payload: Sized {
+    /// Encode the payload into bytes.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Decode the payload from bytes, returns [None] if the bytes are not valid.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Payload for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Payload for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Payload for bool {
+    fn to_bytes(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Implements [Payload] for numbers, encoded in little-endian.
+macro_rules! number_payload {
+    ($($number:ty),*) => {
+        $(
+            impl Payload for $number {
+                fn to_bytes(&self) -> Vec<u8> {
+                    self.to_le_bytes().to_vec()
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Option<Self> {
+                    bytes.try_into().ok().map(<$number>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+number_payload!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
@@ -0,0 +1,355 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use crate::{
+    client::{Client, ClientBuilder},
+    Error, Packet,
+};
+
+/// State of the connection of a [ReconnectingClient], passed to its `state handler`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection attempt started.
+    Connecting,
+    /// The client is connected and the queued packets were sent.
+    Connected,
+    /// The connection was lost, a new one is attempted right away.
+    Disconnected,
+    /// Every attempt to connect failed (see [ClientBuilder::retries]), packets are not queued anymore.
+    GaveUp,
+}
+
+/// Handler called every time the connection of a [ReconnectingClient] changes state.
+/// It runs on its own thread and sees the states in the order they were reached.
+pub type StateHandler = Box<dyn Fn(ConnectionState) + Send + Sync>;
+
+/// State shared between a [ReconnectingClient] and the thread connecting it.
+struct Shared {
+    builder: ClientBuilder,
+    queue_limit: usize,
+    link: Mutex<Link>,
+    changed: Condvar,
+}
+
+/// Connection state guarded by [Shared::link].
+struct Link {
+    state: ConnectionState,
+    /// Client connected by the background thread and not picked up yet.
+    ready: Option<Client>,
+    queue: VecDeque<Packet>,
+    closed: bool,
+    /// States waiting to be passed to the `state handler`.
+    events: Option<Sender<ConnectionState>>,
+}
+
+impl Link {
+    /// Records the new state and queues it for the `state handler`. Callers hold the lock, so
+    /// the handler sees the states in the order they were recorded.
+    fn set_state(&mut self, state: ConnectionState) {
+        self.state = state;
+
+        if let Some(events) = &self.events {
+            let _ = events.send(state);
+        }
+    }
+}
+
+impl Shared {
+    /// Starts a thread connecting a new client.
+    fn spawn_connect(self: &Arc<Self>) {
+        let shared = self.clone();
+        thread::spawn(move || shared.connect());
+    }
+
+    /// Connects a new client and sends the queued packets, then hands it to the [ReconnectingClient].
+    fn connect(&self) {
+        loop {
+            self.set_state(&mut self.link.lock().unwrap(), ConnectionState::Connecting);
+
+            let result = self
+                .builder
+                .open_client(&|| self.link.lock().unwrap().closed);
+
+            let mut link = self.link.lock().unwrap();
+            if link.closed {
+                return;
+            }
+
+            let mut client = match result {
+                Ok(client) => client,
+                Err(_) => {
+                    self.set_state(&mut link, ConnectionState::GaveUp);
+                    return;
+                }
+            };
+
+            // The queue is sent without holding the lock, packets queued meanwhile are sent by the
+            // next round.
+            loop {
+                let mut queue = std::mem::take(&mut link.queue);
+                drop(link);
+
+                let mut lost = false;
+                while let Some(packet) = queue.pop_front() {
+                    if client.send(packet.clone()).is_err() {
+                        queue.push_front(packet);
+                        lost = true;
+                        break;
+                    }
+                }
+
+                link = self.link.lock().unwrap();
+                queue.append(&mut link.queue);
+                link.queue = queue;
+                if link.closed {
+                    let _ = client.disconnect();
+                    return;
+                }
+                if lost {
+                    break;
+                }
+
+                // The client and its state are published together, so a reader losing it right
+                // away cannot record its loss before the connection.
+                if link.queue.is_empty() {
+                    link.ready = Some(client);
+                    self.set_state(&mut link, ConnectionState::Connected);
+                    return;
+                }
+            }
+
+            self.set_state(&mut link, ConnectionState::Disconnected);
+        }
+    }
+
+    /// Records the new state and wakes up readers waiting for a client.
+    fn set_state(&self, link: &mut MutexGuard<'_, Link>, state: ConnectionState) {
+        link.set_state(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Client that reconnects in the background whenever its connection is lost.
+///
+/// While disconnected, sent packets are queued and delivered once the connection is back.
+/// The connection is configured by the [ClientBuilder] given to [ReconnectingClientBuilder::new],
+/// every reconnection uses its retries and backoff.
+pub struct ReconnectingClient {
+    client: Option<Client>,
+    shared: Arc<Shared>,
+}
+
+impl ReconnectingClient {
+    /// Send a [Packet] to the server, or queue it while the client is disconnected.
+    ///
+    /// Returns [Error::QueueFull] if the queue is full and [Error::Disconnected] once the client
+    /// gave up reconnecting.
+    pub fn send(&mut self, packet: Packet) -> Result<(), Error> {
+        loop {
+            if let Some(client) = &mut self.client {
+                match client.send(packet.clone()) {
+                    Ok(_) => return Ok(()),
+                    Err(e @ Error::FrameTooLarge { .. }) => return Err(e),
+                    Err(_) => self.lose(),
+                }
+            }
+
+            let mut link = self.shared.link.lock().unwrap();
+            if let Some(client) = link.ready.take() {
+                self.client = Some(client);
+                continue;
+            }
+
+            if link.state == ConnectionState::GaveUp {
+                return Err(Error::Disconnected);
+            }
+            if link.queue.len() >= self.shared.queue_limit {
+                return Err(Error::QueueFull {
+                    limit: self.shared.queue_limit,
+                });
+            }
+
+            link.queue.push_back(packet);
+            return Ok(());
+        }
+    }
+
+    /// Listen to a [Packet] from the server, waiting for the client to be connected.
+    ///
+    /// An error losing the connection is returned once and a reconnection starts, the next read
+    /// waits for it. Returns [Error::Disconnected] once the client gave up reconnecting.
+    pub fn read(&mut self) -> Result<Packet, Error> {
+        if self.client.is_none() {
+            self.client = Some(self.wait_connected()?);
+        }
+
+        let result = self
+            .client
+            .as_mut()
+            .map_or(Err(Error::Disconnected), Client::read);
+
+        match result {
+            // The stream is still usable after these.
+            Err(Error::Timeout | Error::Decode { .. }) | Ok(_) => result,
+            Err(e) => {
+                self.lose();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the current state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        self.shared.link.lock().unwrap().state
+    }
+
+    /// Returns the number of packets waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.shared.link.lock().unwrap().queue.len()
+    }
+
+    /// Start connecting again after the client gave up, does nothing otherwise.
+    pub fn reconnect(&mut self) {
+        if self.state() == ConnectionState::GaveUp {
+            self.shared.spawn_connect();
+        }
+    }
+
+    /// Close the connection with the server and stop reconnecting.
+    pub fn disconnect(&mut self) {
+        let ready = {
+            let mut link = self.shared.link.lock().unwrap();
+            link.closed = true;
+            link.ready.take()
+        };
+
+        for client in self.client.take().iter().chain(ready.iter()) {
+            let _ = client.disconnect();
+        }
+    }
+
+    /// Internal function, blocks until the background thread connected a client.
+    fn wait_connected(&self) -> Result<Client, Error> {
+        let mut link = self.shared.link.lock().unwrap();
+
+        loop {
+            if let Some(client) = link.ready.take() {
+                return Ok(client);
+            }
+            if link.state == ConnectionState::GaveUp {
+                return Err(Error::Disconnected);
+            }
+            link = self.shared.changed.wait(link).unwrap();
+        }
+    }
+
+    /// Internal function, drops the lost connection and starts a reconnection.
+    fn lose(&mut self) {
+        if let Some(client) = self.client.take() {
+            let _ = client.disconnect();
+        }
+
+        self.shared.set_state(
+            &mut self.shared.link.lock().unwrap(),
+            ConnectionState::Disconnected,
+        );
+        self.shared.spawn_connect();
+    }
+}
+
+impl Drop for ReconnectingClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+/// Reconnecting client builder object.
+/// ```no_run
+/// use bitsock::{
+///     client::ClientBuilder,
+///     reconnect::{ConnectionState, ReconnectingClientBuilder},
+///     Packet,
+/// };
+///
+/// let mut client = ReconnectingClientBuilder::new(ClientBuilder::new().port(4444).retries(10))
+///     .queue_limit(64)
+///     .state_handler(Box::new(|state| {
+///         if state == ConnectionState::GaveUp {
+///             eprintln!("The server is gone.");
+///         }
+///     }))
+///     .connect();
+///
+/// client.send(Packet::String("Hello There!".to_string())).unwrap();
+/// ```
+pub struct ReconnectingClientBuilder {
+    client: ClientBuilder,
+    queue_limit: usize,
+    state_handler: Option<StateHandler>,
+}
+
+impl ReconnectingClientBuilder {
+    /// Creates a new builder, every connection is opened with the given [ClientBuilder].
+    pub fn new(client: ClientBuilder) -> Self {
+        Self {
+            client,
+            queue_limit: 1024,
+            state_handler: None,
+        }
+    }
+
+    /// Sets the maximum number of packets queued while disconnected, 1024 by default.
+    pub fn queue_limit(self, queue_limit: usize) -> Self {
+        Self {
+            queue_limit,
+            ..self
+        }
+    }
+
+    /// Sets the client `state handler`
+    pub fn state_handler(self, handler: StateHandler) -> Self {
+        Self {
+            state_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Build the client and start connecting it in the background.
+    pub fn connect(self) -> ReconnectingClient {
+        // The thread stops once the client and its connecting threads are gone.
+        let events = self.state_handler.map(|handler| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for state in receiver {
+                    handler(state);
+                }
+            });
+            sender
+        });
+
+        let shared = Arc::new(Shared {
+            builder: self.client,
+            queue_limit: self.queue_limit,
+            link: Mutex::new(Link {
+                state: ConnectionState::Connecting,
+                ready: None,
+                queue: VecDeque::new(),
+                closed: false,
+                events,
+            }),
+            changed: Condvar::new(),
+        });
+        shared.spawn_connect();
+
+        ReconnectingClient {
+            client: None,
+            shared,
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    io::ErrorKind,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use crate::{udp::MAX_DATAGRAM_SIZE, Error, Packet, PacketDecodeError};
+
+/// Interval after which a reliable packet that was not acknowledged is sent again.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of sequences past the next expected one a reliable channel keeps. Datagrams further
+/// ahead are dropped without acknowledgement, the peer sends them again later.
+pub const RECEIVE_WINDOW: u32 = 1024;
+
+/// Number of sequences past the oldest unacknowledged one a reliable channel sends, so the peer
+/// keeps every packet in flight. Sending more waits for acknowledgements.
+pub const SEND_WINDOW: u32 = RECEIVE_WINDOW;
+
+/// Number of times a reliable packet is resent before the peer is considered gone, see
+/// [ReliableConnection::set_max_resends].
+pub const MAX_RESENDS: u32 = 50;
+
+/// Maximum number of packets resent at once. The other overdue packets wait [RESEND_PACE], so
+/// losing a whole window does not turn into a burst.
+const RESEND_BURST: usize = 16;
+const RESEND_PACE: Duration = Duration::from_millis(5);
+
+/// Size of the header written before the packet in every datagram: kind, channel and sequence.
+const HEADER_SIZE: usize = 6;
+
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+
+/// Delivery guarantees of the packets sent on a [ReliableConnection].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Packets are sent once, they can be lost, duplicated or arrive out of order.
+    Unreliable,
+    /// Packets are resent until acknowledged and delivered once, in the order they arrive.
+    ReliableUnordered,
+    /// Packets are resent until acknowledged and delivered once, in the order they were sent.
+    ReliableOrdered,
+}
+
+impl Channel {
+    fn id(self) -> u8 {
+        match self {
+            Channel::Unreliable => 0,
+            Channel::ReliableUnordered => 1,
+            Channel::ReliableOrdered => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Channel::Unreliable),
+            1 => Some(Channel::ReliableUnordered),
+            2 => Some(Channel::ReliableOrdered),
+            _ => None,
+        }
+    }
+}
+
+/// Socket exchanging datagrams with a single peer. Implemented by connected [UdpSocket]s, other
+/// implementations can be used to simulate lossy networks.
+pub trait DatagramSocket {
+    /// Send a datagram to the peer.
+    fn send(&self, datagram: &[u8]) -> std::io::Result<usize>;
+
+    /// Receive a datagram from the peer, returning its size.
+    fn recv(&self, buffer: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Sets how long [DatagramSocket::recv] waits for a datagram.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send(&self, datagram: &[u8]) -> std::io::Result<usize> {
+        UdpSocket::send(self, datagram)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        UdpSocket::recv(self, buffer)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+}
+
+/// Reliable packet waiting for its acknowledgement.
+struct Unacked {
+    datagram: Vec<u8>,
+    sent: Instant,
+    resends: u32,
+}
+
+/// Sequences received on the reliable unordered channel: every sequence below `below` and the
+/// ones in `above`.
+#[derive(Default)]
+struct Received {
+    below: u32,
+    above: BTreeSet<u32>,
+}
+
+impl Received {
+    /// Records a sequence, returns false if it was already received.
+    fn insert(&mut self, sequence: u32) -> bool {
+        if ahead(self.below, sequence) < 0 || !self.above.insert(sequence) {
+            return false;
+        }
+
+        while self.above.remove(&self.below) {
+            self.below = self.below.wrapping_add(1);
+        }
+
+        true
+    }
+}
+
+/// Connection sending [Packet]s over datagrams on the three [Channel]s, reliable packets are
+/// resent every [RESEND_INTERVAL] until acknowledged. Once a packet was resent
+/// [MAX_RESENDS] times, the peer is considered gone and every call returns [Error::Disconnected].
+///
+/// There are no background threads: resends happen while [ReliableConnection::send],
+/// [ReliableConnection::read] or [ReliableConnection::flush] are running, acknowledgements are
+/// only received by the last two. Datagrams that cannot be decoded are dropped and counted, see
+/// [ReliableConnection::invalid_datagrams].
+/// ```no_run
+/// use bitsock::{reliable::{Channel, ReliableConnection}, Packet};
+///
+/// let mut connection = ReliableConnection::connect("127.0.0.1", 4445).unwrap();
+/// connection.send(Channel::ReliableOrdered, Packet::String("spawn".to_string())).unwrap();
+/// connection.send(Channel::Unreliable, Packet::F32(0.5)).unwrap();
+///
+/// let (channel, packet) = connection.read().unwrap();
+/// ```
+pub struct ReliableConnection<S: DatagramSocket = UdpSocket> {
+    socket: S,
+    read_timeout: Option<Duration>,
+    next_unordered: u32,
+    next_ordered: u32,
+    unacked: HashMap<(Channel, u32), Unacked>,
+    received_unordered: Received,
+    expected_ordered: u32,
+    early_ordered: BTreeMap<u32, Packet>,
+    delivered: VecDeque<(Channel, Packet)>,
+    invalid_datagrams: u64,
+    buffer: Vec<u8>,
+    max_resends: u32,
+    next_resend: Instant,
+    lost: bool,
+}
+
+impl ReliableConnection<UdpSocket> {
+    /// Bind a local socket and connect it to the peer with given address and port.
+    pub fn connect(address: &str, port: u16) -> Result<Self, Error> {
+        Ok(Self::new(crate::udp::connected_socket(address, port)?))
+    }
+}
+
+impl<S: DatagramSocket> ReliableConnection<S> {
+    /// Creates a connection over a socket already connected to the peer.
+    pub fn new(socket: S) -> Self {
+        Self {
+            socket,
+            read_timeout: None,
+            next_unordered: 0,
+            next_ordered: 0,
+            unacked: HashMap::new(),
+            received_unordered: Received::default(),
+            expected_ordered: 0,
+            early_ordered: BTreeMap::new(),
+            delivered: VecDeque::new(),
+            invalid_datagrams: 0,
+            buffer: crate::udp::receive_buffer(),
+            max_resends: MAX_RESENDS,
+            next_resend: Instant::now(),
+            lost: false,
+        }
+    }
+
+    /// Sets how long [ReliableConnection::read] waits for a packet before returning
+    /// [Error::Timeout], it waits forever by default.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Sets how many times a reliable packet is resent before the peer is considered gone,
+    /// [MAX_RESENDS] by default.
+    pub fn set_max_resends(&mut self, max_resends: u32) {
+        self.max_resends = max_resends;
+    }
+
+    /// Send a [Packet] to the peer on a channel.
+    ///
+    /// Reliable packets wait for acknowledgements while [SEND_WINDOW] packets of the channel are
+    /// in flight, packets received in the meantime are kept for [ReliableConnection::read].
+    pub fn send(&mut self, channel: Channel, packet: Packet) -> Result<(), Error> {
+        if self.lost {
+            return Err(Error::Disconnected);
+        }
+
+        let sequence = match channel {
+            Channel::Unreliable => 0,
+            Channel::ReliableUnordered => self.next_unordered,
+            Channel::ReliableOrdered => self.next_ordered,
+        };
+
+        let mut datagram = header(DATA, channel, sequence);
+        datagram.extend(packet.encode());
+        if datagram.len() > MAX_DATAGRAM_SIZE {
+            return Err(Error::FrameTooLarge {
+                size: datagram.len(),
+                max: MAX_DATAGRAM_SIZE,
+            });
+        }
+
+        if channel == Channel::Unreliable {
+            self.socket.send(&datagram)?;
+            return self.resend();
+        }
+
+        while self.in_flight(channel, sequence) >= SEND_WINDOW {
+            self.poll(None)?;
+        }
+        match channel {
+            Channel::ReliableUnordered => self.next_unordered = sequence.wrapping_add(1),
+            _ => self.next_ordered = sequence.wrapping_add(1),
+        }
+
+        // A failed send is retried like a lost datagram.
+        let _ = self.socket.send(&datagram);
+        self.unacked.insert(
+            (channel, sequence),
+            Unacked {
+                datagram,
+                sent: Instant::now(),
+                resends: 0,
+            },
+        );
+
+        self.resend()
+    }
+
+    /// Listen to a [Packet] from the peer, returning the channel it was sent on.
+    pub fn read(&mut self) -> Result<(Channel, Packet), Error> {
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+
+        loop {
+            if let Some(delivered) = self.delivered.pop_front() {
+                return Ok(delivered);
+            }
+
+            self.poll(deadline)?;
+        }
+    }
+
+    /// Wait until every reliable packet sent so far is acknowledged by the peer.
+    /// Packets received in the meantime are kept for [ReliableConnection::read].
+    pub fn flush(&mut self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+
+        while !self.unacked.is_empty() {
+            self.poll(Some(deadline))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of reliable packets that were not acknowledged yet.
+    pub fn pending(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Returns the number of datagrams received from the peer that were dropped because they
+    /// could not be decoded.
+    pub fn invalid_datagrams(&self) -> u64 {
+        self.invalid_datagrams
+    }
+
+    /// Internal function, returns how many sequences of the channel would be in flight once
+    /// `sequence` is sent.
+    fn in_flight(&self, channel: Channel, sequence: u32) -> u32 {
+        self.unacked
+            .keys()
+            .filter(|(c, _)| *c == channel)
+            .map(|(_, oldest)| ahead(*oldest, sequence) as u32)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Internal function, receives one datagram or resends the packets that were not acknowledged
+    /// in time. Returns [Error::Timeout] once the deadline passed.
+    fn poll(&mut self, deadline: Option<Instant>) -> Result<(), Error> {
+        if self.lost {
+            return Err(Error::Disconnected);
+        }
+
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return Err(Error::Timeout);
+        }
+
+        let resend_at = self
+            .unacked
+            .values()
+            .map(|u| (u.sent + RESEND_INTERVAL).max(self.next_resend))
+            .min();
+        let wake = match (deadline, resend_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // A zero timeout would make the receive block forever.
+        let timeout = wake.map(|wake| {
+            wake.saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        });
+        self.socket.set_read_timeout(timeout)?;
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let received = self.socket.recv(&mut buffer);
+        if let Ok(size) = received {
+            if self.receive(&buffer[..size]).is_err() {
+                self.invalid_datagrams += 1;
+            }
+        }
+        self.buffer = buffer;
+
+        match received {
+            Ok(_) => {}
+            // The peer is not listening yet, reliable packets are resent below.
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
+            Err(e) => match Error::from(e) {
+                Error::Timeout => {}
+                e => return Err(e),
+            },
+        }
+
+        self.resend()
+    }
+
+    /// Internal function, resends the oldest packets that were not acknowledged in time, at most
+    /// [RESEND_BURST] of them every [RESEND_PACE]. Fails once a packet was resent too often.
+    fn resend(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        if now < self.next_resend {
+            return Ok(());
+        }
+
+        let mut overdue: Vec<&mut Unacked> = self
+            .unacked
+            .values_mut()
+            .filter(|unacked| now >= unacked.sent + RESEND_INTERVAL)
+            .collect();
+        overdue.sort_by_key(|unacked| unacked.sent);
+
+        if overdue.len() > RESEND_BURST {
+            self.next_resend = now + RESEND_PACE;
+        }
+        for unacked in overdue.into_iter().take(RESEND_BURST) {
+            if unacked.resends >= self.max_resends {
+                self.lost = true;
+                return Err(Error::Disconnected);
+            }
+
+            let _ = self.socket.send(&unacked.datagram);
+            unacked.sent = now;
+            unacked.resends += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Internal function, handles a datagram received from the peer, fails if it is invalid.
+    fn receive(&mut self, datagram: &[u8]) -> Result<(), Error> {
+        let invalid = Error::Decode {
+            tag: None,
+            offset: 0,
+            error: PacketDecodeError::InvalidLength(datagram.len()),
+        };
+        if datagram.len() < HEADER_SIZE {
+            return Err(invalid);
+        }
+        let channel = match Channel::from_id(datagram[1]) {
+            Some(channel) => channel,
+            None => return Err(invalid),
+        };
+        let sequence = u32::from_le_bytes(datagram[2..HEADER_SIZE].try_into().unwrap());
+
+        match datagram[0] {
+            ACK => {
+                self.unacked.remove(&(channel, sequence));
+                return Ok(());
+            }
+            DATA => {}
+            _ => return Err(invalid),
+        }
+
+        let packet = Packet::decode_slice(&datagram[HEADER_SIZE..])?;
+        let expected = match channel {
+            Channel::Unreliable => None,
+            Channel::ReliableUnordered => Some(self.received_unordered.below),
+            Channel::ReliableOrdered => Some(self.expected_ordered),
+        };
+        if let Some(expected) = expected {
+            // Beyond the window, left unacknowledged so the peer sends it again.
+            if ahead(expected, sequence) >= RECEIVE_WINDOW as i32 {
+                return Ok(());
+            }
+            // Duplicates are acknowledged again, the previous acknowledgement may have been lost.
+            let _ = self.socket.send(&header(ACK, channel, sequence));
+        }
+
+        match channel {
+            Channel::Unreliable => self.delivered.push_back((channel, packet)),
+            Channel::ReliableUnordered => {
+                if self.received_unordered.insert(sequence) {
+                    self.delivered.push_back((channel, packet));
+                }
+            }
+            Channel::ReliableOrdered => {
+                if ahead(self.expected_ordered, sequence) >= 0 {
+                    self.early_ordered.insert(sequence, packet);
+                }
+                while let Some(packet) = self.early_ordered.remove(&self.expected_ordered) {
+                    self.delivered.push_back((channel, packet));
+                    self.expected_ordered = self.expected_ordered.wrapping_add(1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns how far `sequence` is past `expected`, negative if it comes before. Sequences wrap
+/// around, so they are compared as in RFC 1982.
+fn ahead(expected: u32, sequence: u32) -> i32 {
+    sequence.wrapping_sub(expected) as i32
+}
+
+/// Builds the header of a datagram.
+fn header(kind: u8, channel: Channel, sequence: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.push(kind);
+    header.push(channel.id());
+    header.extend_from_slice(&sequence.to_le_bytes());
+
+    header
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    client::Client,
+    split::{PacketReader, PacketWriter},
+    Error, Packet, RPC_ERROR_ID, RPC_REQUEST_ID, RPC_RESPONSE_ID,
+};
+
+/// Handler answering the calls to one method, registered with
+/// [ServerBuilder::rpc_handler](crate::server::ServerBuilder::rpc_handler).
+/// An [Err] is sent back to the caller as [RpcError::Remote].
+pub type RpcHandler = Box<dyn Fn(Packet) -> Result<Packet, String> + Send + Sync>;
+
+/// Handlers of a server, by method id.
+pub(crate) type RpcHandlers = HashMap<u32, RpcHandler>;
+
+/// Error returned when a remote procedure call fails.
+#[derive(Debug)]
+pub enum RpcError {
+    /// Error returned when the call cannot be sent, or the connection is lost before the reply.
+    Connection(Error),
+
+    /// Error returned when no reply arrives in time.
+    Timeout,
+
+    /// Error returned by the handler of the method on the server.
+    Remote(String),
+
+    /// Error returned when the server has no handler for the method.
+    UnknownMethod(u32),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Connection(e) => write!(f, "{}", e),
+            RpcError::Timeout => write!(f, "call timed out"),
+            RpcError::Remote(e) => write!(f, "call failed: {}", e),
+            RpcError::UnknownMethod(method) => write!(f, "unknown method {}", method),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RpcError::Connection(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Kind of the error sent back in a [RPC_ERROR_ID] packet.
+const ERROR_REMOTE: u8 = 0;
+const ERROR_UNKNOWN_METHOD: u8 = 1;
+
+/// Size of the correlation id at the start of every reply.
+const CORRELATION_SIZE: usize = std::mem::size_of::<u64>();
+
+/// Builds a call: the method id, the correlation id, then the encoded payload.
+fn request(method: u32, correlation: u64, payload: &Packet) -> Packet {
+    let mut body = method.to_le_bytes().to_vec();
+    body.extend_from_slice(&correlation.to_le_bytes());
+    body.extend_from_slice(&payload.encode());
+
+    Packet::Identified(RPC_REQUEST_ID, body)
+}
+
+/// Builds a reply: the correlation id, then the encoded result or the error kind and message.
+fn reply(correlation: u64, result: Result<Packet, (u8, String)>) -> Packet {
+    let mut body = correlation.to_le_bytes().to_vec();
+
+    match result {
+        Ok(packet) => {
+            body.extend_from_slice(&packet.encode());
+            Packet::Identified(RPC_RESPONSE_ID, body)
+        }
+        Err((kind, message)) => {
+            body.push(kind);
+            body.extend_from_slice(message.as_bytes());
+            Packet::Identified(RPC_ERROR_ID, body)
+        }
+    }
+}
+
+/// Runs the handler of a call and returns the reply, a malformed call is answered with an error.
+pub(crate) fn answer(handlers: Option<&RpcHandlers>, body: &[u8]) -> Packet {
+    let method = body
+        .get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    let correlation = body
+        .get(4..4 + CORRELATION_SIZE)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()));
+    let (Some(method), Some(correlation)) = (method, correlation) else {
+        // The reply cannot reach the caller, it only tells the peer its call was not understood.
+        let message = "malformed call".to_string();
+        return reply(
+            correlation.unwrap_or_default(),
+            Err((ERROR_REMOTE, message)),
+        );
+    };
+
+    let result = match Packet::decode_slice(&body[4 + CORRELATION_SIZE..]) {
+        Err(e) => Err((ERROR_REMOTE, format!("malformed call: {}", e))),
+        Ok(payload) => match handlers.and_then(|handlers| handlers.get(&method)) {
+            Some(handler) => handler(payload).map_err(|e| (ERROR_REMOTE, e)),
+            None => Err((ERROR_UNKNOWN_METHOD, String::new())),
+        },
+    };
+
+    reply(correlation, result)
+}
+
+/// Calls waiting for their reply with their method id, by correlation id.
+struct Pending {
+    calls: HashMap<u64, (u32, Sender<Result<Packet, RpcError>>)>,
+    closed: bool,
+}
+
+/// Client calling the methods registered on a server, any number of calls can be in flight at
+/// once from different threads. Packets that are not replies can still be read with
+/// [RpcClient::read].
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use bitsock::{client::Client, rpc::RpcClient, Packet};
+///
+/// let client = RpcClient::new(Client::connect("127.0.0.1", 4444).unwrap()).unwrap();
+/// let sum = client.call(1, Packet::Bytes(vec![2, 3]), Duration::from_secs(1));
+/// ```
+pub struct RpcClient {
+    writer: PacketWriter,
+    pending: Arc<Mutex<Pending>>,
+    next_correlation: AtomicU64,
+    packets: Mutex<Receiver<Result<Packet, Error>>>,
+    receiving: Option<JoinHandle<()>>,
+}
+
+impl RpcClient {
+    /// Wraps a connected client, a thread is started to receive the replies.
+    pub fn new(client: Client) -> Result<Self, Error> {
+        let (reader, writer) = client.split()?;
+        let pending = Arc::new(Mutex::new(Pending {
+            calls: HashMap::new(),
+            closed: false,
+        }));
+        let (sender, packets) = mpsc::channel();
+
+        let receiving = pending.clone();
+        let receiving = thread::spawn(move || receive(reader, receiving, sender));
+
+        Ok(Self {
+            writer,
+            pending,
+            next_correlation: AtomicU64::new(0),
+            packets: Mutex::new(packets),
+            receiving: Some(receiving),
+        })
+    }
+
+    /// Call a method on the server and wait for its reply for at most `timeout`.
+    pub fn call(
+        &self,
+        method: u32,
+        payload: Packet,
+        timeout: Duration,
+    ) -> Result<Packet, RpcError> {
+        let correlation = self.next_correlation.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed {
+                return Err(RpcError::Connection(Error::Disconnected));
+            }
+            pending.calls.insert(correlation, (method, sender));
+        }
+
+        if let Err(e) = self.writer.send(request(method, correlation, &payload)) {
+            self.pending.lock().unwrap().calls.remove(&correlation);
+            return Err(RpcError::Connection(e));
+        }
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => {
+                self.pending.lock().unwrap().calls.remove(&correlation);
+                Err(RpcError::Timeout)
+            }
+        }
+    }
+
+    /// Send a [Packet] to the server.
+    pub fn send(&self, packet: Packet) -> Result<usize, Error> {
+        self.writer.send(packet)
+    }
+
+    /// Listen to a [Packet] from the server that is not a reply.
+    /// Returns [Error::Disconnected] once the connection is closed.
+    pub fn read(&self) -> Result<Packet, Error> {
+        match self.packets.lock().unwrap().recv() {
+            Ok(result) => result,
+            Err(_) => Err(Error::Disconnected),
+        }
+    }
+
+    /// Close the connection with the server, calls in flight fail.
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.writer.disconnect()
+    }
+}
+
+impl Drop for RpcClient {
+    fn drop(&mut self) {
+        let _ = self.disconnect();
+        if let Some(receiving) = self.receiving.take() {
+            let _ = receiving.join();
+        }
+    }
+}
+
+/// Reads the packets of an [RpcClient], passing replies to their call and the others to [RpcClient::read].
+fn receive(
+    mut reader: PacketReader,
+    pending: Arc<Mutex<Pending>>,
+    packets: Sender<Result<Packet, Error>>,
+) {
+    let error = loop {
+        let (id, body) = match reader.read() {
+            Ok(Packet::Identified(id, body)) if id == RPC_RESPONSE_ID || id == RPC_ERROR_ID => {
+                (id, body)
+            }
+            Ok(packet) => {
+                let _ = packets.send(Ok(packet));
+                continue;
+            }
+            Err(e) => break e,
+        };
+
+        let correlation = match body.get(..CORRELATION_SIZE) {
+            Some(bytes) => u64::from_le_bytes(bytes.try_into().unwrap()),
+            None => continue,
+        };
+        let (method, call) = match pending.lock().unwrap().calls.remove(&correlation) {
+            Some(call) => call,
+            // The call already timed out.
+            None => continue,
+        };
+
+        let rest = &body[CORRELATION_SIZE..];
+        let result = if id == RPC_RESPONSE_ID {
+            Packet::decode_slice(rest).map_err(RpcError::Connection)
+        } else {
+            match rest.split_first() {
+                Some((&ERROR_UNKNOWN_METHOD, _)) => Err(RpcError::UnknownMethod(method)),
+                Some((_, message)) => Err(RpcError::Remote(
+                    String::from_utf8_lossy(message).into_owned(),
+                )),
+                None => Err(RpcError::Remote(String::new())),
+            }
+        };
+        let _ = call.send(result);
+    };
+
+    let mut pending = pending.lock().unwrap();
+    pending.closed = true;
+    for (_, (_, call)) in pending.calls.drain() {
+        let _ = call.send(Err(RpcError::Connection(Error::Disconnected)));
+    }
+    let _ = packets.send(Err(error));
+}
@@ -1,84 +1,857 @@
 use std::{
-    fmt::{self},
-    io::{Read, Write},
-    net::{Shutdown, TcpListener, TcpStream},
-    sync::Mutex,
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    net::{Shutdown, SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, SystemTime},
 };
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "rustls")]
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{LogLevel, LogStage, Packet, ReadingError};
+#[cfg(feature = "serde")]
+use crate::message::{MessageCodec, MessageError};
+use crate::{
+    auth::{self, AuthHandler, AUTH_TIMEOUT},
+    extensions::Extensions,
+    hello::{self, Capabilities, Hello, HELLO_TIMEOUT},
+    rpc::{RpcHandler, RpcHandlers},
+    split::{self, PacketReader, PacketWriter},
+    stream::{Connection, ConnectionOptions, Heartbeat, Socket, Stream, StreamWriter},
+    Error, LogLevel, LogStage, Packet,
+};
 
-/// Error type for handling physical server errors.
+/// Handler called with every error propagated by the physical server.
 ///
 /// ```
-/// // Using ServerError in a custom handler
-///
-/// fn handle_server_errors(error: ServerError) {
-///     eprintln!("[SERVER][ERROR]: {}", error);
-///     
-///     // do some other logic...
-///
-/// }
+/// // Using a custom error handler
+/// use bitsock::{server::ServerBuilder, Error};
 ///
+/// let server = ServerBuilder::new()
+///     .error_handler(Box::new(|error| match error {
+///         Error::Bind { address, .. } => eprintln!("[SERVER][ERROR]: {} is busy", address),
+///         error => eprintln!("[SERVER][ERROR]: {}", error),
+///     }))
+///     .build();
 /// ```
-#[derive(Clone, Debug)]
-pub struct ServerError(String);
+pub type ErrorHandler = Box<dyn Fn(Error) + Send + Sync>;
 
-impl fmt::Display for ServerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
+/// Handler called with every [LogicalClient] that connects to the physical server.
+pub type ClientHandler = Box<dyn Fn(LogicalClient) + Send + Sync>;
+
+/// Handler called with every log produced by the physical server.
+pub type LogHandler = Box<dyn Fn(LogStage, LogLevel, &str) + Send + Sync>;
+
+/// Handler called with the address of every client whose connection ended.
+pub type DisconnectHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Certificate chain and private key used to accept TLS connections.
+#[cfg(feature = "rustls")]
+type TlsIdentity = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+/// Interval at which the accept loop checks whether the server is shutting down.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a refused client has to send its hello before it is turned away anyway.
+const REFUSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a new client can stay silent before it is taken as a native client, see
+/// [ServerBuilder::websocket].
+#[cfg(feature = "websocket")]
+const WEBSOCKET_DETECT_TIMEOUT: Duration = Duration::from_millis(200);
 
 /// Logical client data structure.
 pub struct LogicalClient {
+    id: u64,
     address: String,
-    stream: TcpStream,
+    peer_addr: Option<SocketAddr>,
+    connected_at: SystemTime,
+    negotiated: Hello,
+    auth_timeout: Duration,
+    extensions: Arc<Mutex<Extensions>>,
+    connection: Connection,
+    shared: Arc<Shared>,
+    #[cfg(feature = "serde")]
+    codec: MessageCodec,
 }
 
 impl LogicalClient {
     /// Send a [Packet] to the client.
-    pub fn send(&mut self, packet: Packet) -> Result<usize, std::io::Error> {
-        let size = self.stream.write(packet.encode().as_slice())?;
-        Ok(size)
+    pub fn send(&mut self, packet: Packet) -> Result<usize, Error> {
+        self.connection.send(&packet)
     }
 
     /// Listen to a [Packet] from the client.
-    pub fn read(&mut self) -> Result<Packet, ReadingError> {
-        let mut data = [0; 64];
-
-        match self.stream.read(&mut data) {
-            Ok(_) => {
-                if let Ok(packet) = Packet::decode(data.to_vec()) {
-                    Ok(packet)
-                } else {
-                    Err(ReadingError::Decode)
-                }
-            }
-            Err(_) => Err(ReadingError::Reading),
+    /// Returns [Error::Disconnected] once the client closed the connection.
+    ///
+    /// Returns [Error::Timeout] if the read timeout expires, or if the heartbeat timeout expires,
+    /// in which case the client is dropped and reported to the server `error handler`.
+    pub fn read(&mut self) -> Result<Packet, Error> {
+        let result = self.connection.read();
+        self.drop_if_idle();
+
+        result
+    }
+
+    /// Listen to a [Packet] from the client like [LogicalClient::read], returning
+    /// [Error::Timeout] after `timeout` instead of the read timeout.
+    pub fn read_within(&mut self, timeout: Duration) -> Result<Packet, Error> {
+        let result = self.connection.read_within(timeout);
+        self.drop_if_idle();
+
+        result
+    }
+
+    fn drop_if_idle(&self) {
+        if self.connection.is_idle() {
+            let _ = self.disconnect();
+            self.shared.drop_idle(&self.address);
         }
     }
 
+    /// Get the id of the connection, used to reach the client through a [ServerHandle].
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     /// Get the address of the client.
     pub fn address(&self) -> String {
         self.address.clone()
     }
 
+    /// Get the address of the client as a [SocketAddr], [None] for clients connected over a Unix
+    /// domain socket.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Get the time at which the client connected.
+    pub fn connected_at(&self) -> SystemTime {
+        self.connected_at
+    }
+
+    /// Get the settings agreed with the client when it connected.
+    pub fn negotiated(&self) -> &Hello {
+        &self.negotiated
+    }
+
+    /// Returns how long an `auth handler` should wait for each reply of the client, set with
+    /// [ServerBuilder::auth_timeout].
+    pub fn auth_timeout(&self) -> Duration {
+        self.auth_timeout
+    }
+
+    /// Get the traffic exchanged with the client so far, including what was sent to it through the
+    /// [ServerHandle] and the halves returned by [LogicalClient::split].
+    pub fn stats(&self) -> ConnectionStats {
+        self.connection.stats()
+    }
+
+    /// Returns the session data attached to the client, also reachable from other threads with
+    /// [ServerHandle::with_extensions]. The map is locked until the guard is dropped.
+    pub fn extensions(&self) -> MutexGuard<'_, Extensions> {
+        self.extensions.lock().unwrap()
+    }
+
+    /// Join a room, the client receives every packet published to it until it leaves or disconnects.
+    pub fn join(&self, room: &str) -> Result<(), Error> {
+        self.shared.join(self.id, room)
+    }
+
+    /// Leave a room.
+    pub fn leave(&self, room: &str) {
+        self.shared.leave(self.id, room);
+    }
+
+    /// Send a [Packet] to every client in a room, including this one if it joined it.
+    /// Returns the number of clients it was sent to.
+    pub fn publish(&self, room: &str, packet: Packet) -> Result<usize, Error> {
+        self.server().publish(room, packet)
+    }
+
+    /// Split the client into a [PacketReader] and a [PacketWriter], so one thread can wait for
+    /// packets while others send them. The client stays registered until the `client handler`
+    /// returns.
+    pub fn split(self) -> Result<(PacketReader, PacketWriter), Error> {
+        let address = self.address;
+        let shared = self.shared;
+
+        split::split(
+            self.connection,
+            Box::new(move || shared.drop_idle(&address)),
+            #[cfg(feature = "serde")]
+            self.codec,
+        )
+    }
+
+    /// Returns a [ServerHandle] of the server handling the client, to reach the other clients.
+    pub fn server(&self) -> ServerHandle {
+        ServerHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Returns true once the server is shutting down, the handler should finish its work and return.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.stopping.load(Ordering::SeqCst)
+    }
+
     /// Close the connection with the client.
-    pub fn disconnect(&self) -> Result<(), std::io::Error> {
-        self.stream.shutdown(Shutdown::Both)?;
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.connection.shutdown(Shutdown::Both)?;
 
         Ok(())
     }
+
+    /// Internal function, exchanges hellos with the client. A client the server cannot talk to is
+    /// disconnected.
+    fn negotiate(&mut self, hello: &Hello, timeout: Duration) -> bool {
+        match hello::accept(&mut self.connection, hello, timeout) {
+            Ok(agreed) => {
+                self.negotiated = agreed;
+                true
+            }
+            Err(e) => {
+                self.shared.log(
+                    LogLevel::WARN,
+                    &format!("Handshake with client {} failed: {}.", self.address, e),
+                );
+                let _ = self.disconnect();
+                false
+            }
+        }
+    }
+
+    /// Internal function, runs the `auth handler` and tells the client the outcome.
+    /// A rejected client is disconnected.
+    fn authenticate(&mut self, handler: &AuthHandler) -> bool {
+        let result = handler(self);
+        let _ = self.send(auth::result_packet(&result));
+
+        match result {
+            Ok(()) => true,
+            Err(reason) => {
+                self.shared.log(
+                    LogLevel::WARN,
+                    &format!(
+                        "Client {} failed to authenticate: {}.",
+                        self.address, reason
+                    ),
+                );
+                let _ = self.disconnect();
+                false
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl LogicalClient {
+    /// Sets the codec used by [LogicalClient::send_message] and [LogicalClient::read_message].
+    pub fn set_message_codec(&mut self, codec: MessageCodec) {
+        self.codec = codec;
+    }
+
+    /// Serialize a message and send it to the client.
+    pub fn send_message<T: Serialize>(&mut self, message: &T) -> Result<usize, MessageError> {
+        self.send(self.codec.to_packet(message)?)
+            .map_err(MessageError::Connection)
+    }
+
+    /// Listen to a message from the client and deserialize it.
+    pub fn read_message<T: DeserializeOwned>(&mut self) -> Result<T, MessageError> {
+        let packet = self.read().map_err(MessageError::Connection)?;
+        self.codec.from_packet(packet)
+    }
+}
+
+/// Policy applied to new connections while the server is already handling
+/// [ServerBuilder::max_connections] clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Keep the connection waiting until a running handler finishes.
+    Queue,
+    /// Refuse the hello of the client saying the server is full, so connecting fails with
+    /// [Error::Handshake], then close the connection.
+    Reject,
+    /// Close the connection without notifying the client.
+    Close,
+}
+
+/// Counter of the clients being handled, used to enforce the maximum concurrency.
+struct Slots {
+    limit: Option<usize>,
+    active: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            active: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Take a slot if one is free, when `wait` is true block until one is or `stopping` is set.
+    fn acquire(&self, wait: bool, stopping: &AtomicBool) -> Option<SlotGuard<'_>> {
+        let mut active = self.active.lock().unwrap();
+
+        if let Some(limit) = self.limit {
+            while *active >= limit {
+                if !wait || stopping.load(Ordering::SeqCst) {
+                    return None;
+                }
+                active = self.freed.wait(active).unwrap();
+            }
+        }
+
+        *active += 1;
+        Some(SlotGuard(self))
+    }
+
+    /// Block until every slot is released or the timeout expires, returns true if all were released.
+    fn wait_idle(&self, timeout: Duration) -> bool {
+        let active = self.active.lock().unwrap();
+        let (active, _) = self
+            .freed
+            .wait_timeout_while(active, timeout, |active| *active > 0)
+            .unwrap();
+
+        *active == 0
+    }
+}
+
+/// Slot taken by a running client handler, released when dropped (even if the handler panics).
+struct SlotGuard<'s>(&'s Slots);
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.active.lock().unwrap() -= 1;
+        self.0.freed.notify_all();
+    }
+}
+
+/// Registration of a client whose handler is running, removed when dropped (even if the handler
+/// panics) before the `disconnect handler` is called.
+struct RegistrationGuard<'s> {
+    shared: &'s Shared,
+    id: u64,
+    address: String,
+    disconnect_handler: Option<&'s DisconnectHandler>,
+}
+
+impl Drop for RegistrationGuard<'_> {
+    fn drop(&mut self) {
+        self.shared.unregister(self.id);
+
+        if let Some(handler) = self.disconnect_handler {
+            handler(&self.address);
+        }
+    }
+}
+
+/// State shared between a [Server], its [ServerHandle]s and its [LogicalClient]s.
+struct Shared {
+    stopping: AtomicBool,
+    slots: Slots,
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<u64, Registered>>,
+    authenticating: Mutex<HashMap<u64, Socket>>,
+    rooms: Mutex<HashMap<String, HashSet<u64>>>,
+    error_handler: Option<ErrorHandler>,
+    log_handler: Option<LogHandler>,
+}
+
+impl Shared {
+    fn new(
+        max_connections: Option<usize>,
+        error_handler: Option<ErrorHandler>,
+        log_handler: Option<LogHandler>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            stopping: AtomicBool::new(false),
+            slots: Slots::new(max_connections),
+            next_id: AtomicU64::new(0),
+            connections: Mutex::new(HashMap::new()),
+            authenticating: Mutex::new(HashMap::new()),
+            rooms: Mutex::new(HashMap::new()),
+            error_handler,
+            log_handler,
+        })
+    }
+
+    /// Keeps a client that did not complete its handshake yet out of the registry, only its socket
+    /// is kept so a shutdown can interrupt the handshake.
+    fn hold(&self, client: &LogicalClient) {
+        let socket = match client.connection.socket().try_clone() {
+            Ok(socket) => socket,
+            Err(e) => return self.handle_error(Error::Io(e)),
+        };
+
+        let mut authenticating = self.authenticating.lock().unwrap();
+        if self.stopping.load(Ordering::SeqCst) {
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+        authenticating.insert(client.id, socket);
+    }
+
+    /// Adds a client to the registry, closing its read half right away if the server is stopping.
+    fn register(&self, client: &LogicalClient) {
+        let socket = match client.connection.socket().try_clone() {
+            Ok(socket) => socket,
+            Err(e) => return self.handle_error(Error::Io(e)),
+        };
+
+        self.authenticating.lock().unwrap().remove(&client.id);
+        let mut connections = self.connections.lock().unwrap();
+        if self.stopping.load(Ordering::SeqCst) {
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+        connections.insert(
+            client.id,
+            Registered {
+                address: client.address.clone(),
+                peer_addr: client.peer_addr,
+                connected_at: client.connected_at,
+                extensions: client.extensions.clone(),
+                socket,
+                writer: client.connection.writer().clone(),
+            },
+        );
+    }
+
+    /// Removes a client from the registry and from every room it joined.
+    fn unregister(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+        self.authenticating.lock().unwrap().remove(&id);
+
+        self.rooms.lock().unwrap().retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+
+    /// Returns the writer of a registered client.
+    fn writer(&self, id: u64) -> Result<StreamWriter, Error> {
+        match self.connections.lock().unwrap().get(&id) {
+            Some(registered) => Ok(registered.writer.clone()),
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// Writes a frame to every registered client in `ids`, returns how many were written to.
+    fn write_to_all<'i>(&self, ids: impl IntoIterator<Item = &'i u64>, frame: &[u8]) -> usize {
+        let writers: Vec<StreamWriter> = {
+            let connections = self.connections.lock().unwrap();
+            ids.into_iter()
+                .filter_map(|id| connections.get(id))
+                .map(|registered| registered.writer.clone())
+                .collect()
+        };
+
+        writers
+            .iter()
+            .filter(|writer| writer.write_frame(frame).is_ok())
+            .count()
+    }
+
+    /// Adds a registered client to a room.
+    fn join(&self, id: u64, room: &str) -> Result<(), Error> {
+        let connections = self.connections.lock().unwrap();
+        if !connections.contains_key(&id) {
+            return Err(Error::Disconnected);
+        }
+
+        self.rooms
+            .lock()
+            .unwrap()
+            .entry(room.to_string())
+            .or_default()
+            .insert(id);
+
+        Ok(())
+    }
+
+    /// Removes a client from a room, the room is dropped once empty.
+    fn leave(&self, id: u64, room: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+
+        if let Some(members) = rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                rooms.remove(room);
+            }
+        }
+    }
+
+    /// Reports a client dropped because it stopped answering the heartbeat.
+    fn drop_idle(&self, address: &str) {
+        self.log(
+            LogLevel::WARN,
+            &format!("Client {} stopped answering, dropping it.", address),
+        );
+        self.handle_error(Error::Idle {
+            address: address.to_string(),
+        });
+    }
+
+    /// Passes an error to the `error handler`, or prints it.
+    fn handle_error(&self, error: Error) {
+        if let Some(handler) = &self.error_handler {
+            handler(error);
+        } else {
+            println!("{}", crate::error::report(&error));
+        }
+    }
+
+    /// Passes a log to the `logger`, or prints it.
+    fn log(&self, level: LogLevel, message: &str) {
+        if let Some(handler) = &self.log_handler {
+            handler(LogStage::SERVER, level, message);
+        } else {
+            println!("[SERVER][{:?}]: {}", level, message);
+        }
+    }
+}
+
+/// Client in the registry of a [Server], reachable from outside its `client handler`.
+struct Registered {
+    address: String,
+    peer_addr: Option<SocketAddr>,
+    connected_at: SystemTime,
+    extensions: Arc<Mutex<Extensions>>,
+    socket: Socket,
+    writer: StreamWriter,
+}
+
+/// Information about a client connected to a [Server], returned by [ServerHandle::clients].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub address: String,
+    pub peer_addr: Option<SocketAddr>,
+    pub connected_at: SystemTime,
+    pub stats: ConnectionStats,
+}
+
+/// Traffic exchanged with a peer. Bytes are counted as bitsock frames, before TLS or WebSocket
+/// framing, and include the hello, the heartbeat and remote procedure calls. Heartbeat packets are
+/// left out of the packet counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+}
+
+/// Handle used to reach the clients of a running [Server] and to stop it from any thread,
+/// obtained with [Server::handle] or [LogicalClient::server].
+#[derive(Clone)]
+pub struct ServerHandle {
+    shared: Arc<Shared>,
+}
+
+impl ServerHandle {
+    /// Gracefully stop the server.
+    ///
+    /// The server stops accepting connections and the read half of every client stream is closed,
+    /// so handlers blocked in [LogicalClient::read] get an error and can finish. Once every handler
+    /// returned, or after `timeout`, the remaining streams are closed and [Server::run] returns.
+    /// Returns true if every handler finished before the timeout.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        self.shared.slots.freed.notify_all();
+
+        for registered in self.shared.connections.lock().unwrap().values() {
+            let _ = registered.socket.shutdown(Shutdown::Read);
+        }
+        for socket in self.shared.authenticating.lock().unwrap().values() {
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+
+        let finished = self.shared.slots.wait_idle(timeout);
+
+        if !finished {
+            for registered in self.shared.connections.lock().unwrap().values() {
+                let _ = registered.socket.shutdown(Shutdown::Both);
+            }
+            for socket in self.shared.authenticating.lock().unwrap().values() {
+                let _ = socket.shutdown(Shutdown::Both);
+            }
+        }
+
+        finished
+    }
+
+    /// Returns true once [ServerHandle::shutdown] has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Send a [Packet] to every connected client, returns the number of clients it was sent to.
+    /// Clients that cannot be written to are skipped, their handler gets the error when reading.
+    pub fn broadcast(&self, packet: Packet) -> Result<usize, Error> {
+        let frame = packet.checked_frame()?;
+        let ids: Vec<u64> = self
+            .shared
+            .connections
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+
+        Ok(self.shared.write_to_all(&ids, &frame))
+    }
+
+    /// Send a [Packet] to the client with the given connection id.
+    /// Returns [Error::Disconnected] if no connected client has this id.
+    pub fn send_to(&self, id: u64, packet: Packet) -> Result<usize, Error> {
+        self.shared.writer(id)?.send(&packet)
+    }
+
+    /// Close the connection of the client with the given connection id, its handler gets
+    /// [Error::Disconnected] on the next read. Returns [Error::Disconnected] if no connected client
+    /// has this id.
+    pub fn disconnect(&self, id: u64) -> Result<(), Error> {
+        match self.shared.connections.lock().unwrap().get(&id) {
+            Some(registered) => Ok(registered.socket.shutdown(Shutdown::Both)?),
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// Call `f` with the session data attached to the client with the given connection id, see
+    /// [LogicalClient::extensions]. Returns [Error::Disconnected] if no connected client has this id.
+    pub fn with_extensions<R>(
+        &self,
+        id: u64,
+        f: impl FnOnce(&mut Extensions) -> R,
+    ) -> Result<R, Error> {
+        let extensions = match self.shared.connections.lock().unwrap().get(&id) {
+            Some(registered) => registered.extensions.clone(),
+            None => return Err(Error::Disconnected),
+        };
+        let mut extensions = extensions.lock().unwrap();
+
+        Ok(f(&mut extensions))
+    }
+
+    /// Add the client with the given connection id to a room, the room is created if needed.
+    /// Returns [Error::Disconnected] if no connected client has this id.
+    pub fn join(&self, id: u64, room: &str) -> Result<(), Error> {
+        self.shared.join(id, room)
+    }
+
+    /// Remove the client with the given connection id from a room.
+    pub fn leave(&self, id: u64, room: &str) {
+        self.shared.leave(id, room);
+    }
+
+    /// Send a [Packet] to every client in a room, returns the number of clients it was sent to.
+    pub fn publish(&self, room: &str, packet: Packet) -> Result<usize, Error> {
+        let frame = packet.checked_frame()?;
+        let ids: Vec<u64> = match self.shared.rooms.lock().unwrap().get(room) {
+            Some(members) => members.iter().copied().collect(),
+            None => return Ok(0),
+        };
+
+        Ok(self.shared.write_to_all(&ids, &frame))
+    }
+
+    /// Returns the connection ids of the clients in a room, sorted.
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        let mut members: Vec<u64> = match self.shared.rooms.lock().unwrap().get(room) {
+            Some(members) => members.iter().copied().collect(),
+            None => Vec::new(),
+        };
+        members.sort_unstable();
+
+        members
+    }
+
+    /// Returns the names of the rooms with at least one client, sorted.
+    pub fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = self.shared.rooms.lock().unwrap().keys().cloned().collect();
+        rooms.sort();
+
+        rooms
+    }
+
+    /// Returns the clients connected to the server, sorted by connection id.
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients: Vec<ClientInfo> = self
+            .shared
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, registered)| ClientInfo {
+                id: *id,
+                address: registered.address.clone(),
+                peer_addr: registered.peer_addr,
+                connected_at: registered.connected_at,
+                stats: registered.writer.counters().stats(),
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+
+        clients
+    }
+}
+
+/// Listener accepting the clients of a [Server].
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), std::io::Error> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Accepts a client, returning its stream in blocking mode and its address.
+    fn accept(&self) -> Result<(Stream, String, Option<SocketAddr>), std::io::Error> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, address) = listener.accept()?;
+                let _ = stream.set_nonblocking(false);
+
+                Ok((Stream::Tcp(stream), address.to_string(), Some(address)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                let (stream, _) = listener.accept()?;
+                let _ = stream.set_nonblocking(false);
+
+                Ok((
+                    Stream::Unix(stream),
+                    format!("unix:{}", path.display()),
+                    None,
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Binds a Unix listener, removing the socket file left behind by a server that did not stop
+/// cleanly and setting the permissions of the new one.
+#[cfg(unix)]
+fn bind_unix(path: &Path, permissions: Option<u32>) -> Result<Listener, Error> {
+    let error = |source| Error::Bind {
+        address: path.display().to_string(),
+        source,
+    };
+
+    remove_stale_socket(path).map_err(error)?;
+    let listener = match permissions {
+        Some(mode) => bind_private(path, mode).map_err(error)?,
+        None => UnixListener::bind(path).map_err(error)?,
+    };
+
+    Ok(Listener::Unix(listener, path.to_path_buf()))
+}
+
+/// Binds the socket in a directory only the owner can enter, then moves it to `path` once its
+/// permissions are set, so no one can connect while the socket has the permissions of the umask.
+#[cfg(unix)]
+fn bind_private(path: &Path, mode: u32) -> Result<UnixListener, std::io::Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::from(ErrorKind::InvalidInput))?;
+    let mut private = name.to_os_string();
+    private.push(format!(".{}", std::process::id()));
+    let private = path.with_file_name(private);
+    let staged = private.join("s");
+
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let listener = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&private);
+
+    listener
+}
+
+/// Removes the socket file at `path` if no server is listening on it anymore.
+/// Fails if the path is not a socket or if a server is still listening on it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), std::io::Error> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => return Err(std::io::Error::from(ErrorKind::AlreadyExists)),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::from(ErrorKind::AddrInUse)),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(e) => Err(e),
+    }
 }
 
 /// Physical server data structure.
 pub struct Server<'a> {
     pub address: &'a str,
     pub port: u16,
-    listener: Option<TcpListener>,
-    error_handler: Option<Box<dyn Fn(ServerError) -> () + Send + Sync>>,
-    client_handler: Box<dyn Fn(LogicalClient) -> () + Send + Sync>,
-    log_handler: Option<Box<dyn Fn(LogStage, LogLevel, &str) + Send + Sync>>,
+    #[cfg(unix)]
+    unix_path: Option<PathBuf>,
+    #[cfg(unix)]
+    unix_permissions: Option<u32>,
+    listener: Option<Listener>,
+    overflow_policy: OverflowPolicy,
+    #[cfg(feature = "websocket")]
+    websocket: bool,
+    options: ConnectionOptions,
+    rpc_handlers: Arc<RpcHandlers>,
+    shared: Arc<Shared>,
+    #[cfg(feature = "rustls")]
+    tls: Option<TlsIdentity>,
+    hello: Hello,
+    hello_timeout: Duration,
+    auth_timeout: Duration,
+    auth_handler: Option<AuthHandler>,
+    client_handler: ClientHandler,
+    disconnect_handler: Option<DisconnectHandler>,
 }
 
 impl<'a> Server<'a> {
@@ -87,90 +860,330 @@ impl<'a> Server<'a> {
         Self {
             address,
             port,
+            #[cfg(unix)]
+            unix_path: None,
+            #[cfg(unix)]
+            unix_permissions: None,
             listener: None,
-            error_handler: None,
-            client_handler: Box::new(|c| println!("{} connected.", c.address())),
-            log_handler: None,
+            overflow_policy: OverflowPolicy::Queue,
+            #[cfg(feature = "websocket")]
+            websocket: false,
+            options: ConnectionOptions::default(),
+            rpc_handlers: Arc::new(HashMap::new()),
+            shared: Shared::new(None, None, None),
+            #[cfg(feature = "rustls")]
+            tls: None,
+            hello: Hello::default(),
+            hello_timeout: HELLO_TIMEOUT,
+            auth_timeout: AUTH_TIMEOUT,
+            auth_handler: None,
+            client_handler: default_client_handler(false),
+            disconnect_handler: None,
+        }
+    }
+
+    /// Returns a [ServerHandle] that can be used from other threads to stop the server.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Bind the server without accepting clients yet, so [Server::local_addr] is known before
+    /// [Server::run] is called. [Server::run] binds the server itself otherwise.
+    pub fn listen(&mut self) -> Result<(), Error> {
+        if self.listener.is_none() {
+            self.listener = Some(self.bind()?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the address the server is bound to, to find the port picked for port `0`.
+    /// Returns [None] until the server is bound, and for Unix sockets.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Some(Listener::Tcp(listener)) => listener.local_addr().ok(),
+            _ => None,
         }
     }
 
     /// Start the server execution, this will start a loop.
+    /// Every connected client is handled by the `client handler` on its own thread.
     pub fn run(&mut self) {
         self.log(LogLevel::INFO, "Starting server");
 
-        self.listener =
-            if let Ok(listener) = TcpListener::bind(format!("{}:{}", self.address, self.port)) {
-                Some(listener)
-            } else {
-                self.handle_error(ServerError(format!(
-                    "failed to bind listener to address {}:{}",
-                    self.address, self.port,
-                )));
-                None
-            };
-
-        let handler = Mutex::new(&self.client_handler);
-
-        if let Err(_) = crossbeam::thread::scope(|s| {
-            s.spawn(|_| {
-                if let Some(listener) = &self.listener {
-                    self.log(LogLevel::INFO, "Server started, listening for connections.");
-
-                    for stream in listener.incoming() {
-                        match stream {
-                            Ok(stream) => {
-                                let client = LogicalClient {
-                                    address: stream.local_addr().unwrap().to_string(),
-                                    stream,
-                                };
-                                handler.lock().unwrap()(client);
-                            }
-                            Err(e) => {
-                                self.handle_error(ServerError(format!("Connection failed: {}", e)))
+        #[cfg(feature = "rustls")]
+        let tls = match self.tls_config() {
+            Ok(tls) => tls,
+            Err(e) => {
+                self.handle_error(e);
+                self.listener = None;
+                return;
+            }
+        };
+
+        if let Err(e) = self.listen() {
+            self.handle_error(e);
+        }
+
+        let hello = &self.hello;
+        let hello_timeout = self.hello_timeout;
+        let auth_handler = &self.auth_handler;
+        let handler = &self.client_handler;
+        let disconnect_handler = &self.disconnect_handler;
+        let shared = &self.shared;
+        #[cfg(feature = "websocket")]
+        let websocket = self.websocket;
+
+        if crossbeam::thread::scope(|s| {
+            if let Some(listener) = &self.listener {
+                if let Err(e) = listener.set_nonblocking(true) {
+                    self.handle_error(Error::Io(e));
+                    return;
+                }
+
+                self.log(LogLevel::INFO, "Server started, listening for connections.");
+
+                while !self.shared.stopping.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, address, peer_addr)) => {
+                            let id = self.shared.next_id.fetch_add(1, Ordering::SeqCst);
+
+                            #[cfg(feature = "rustls")]
+                            let stream = match accept_tls(&tls, stream) {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    self.handle_error(e);
+                                    continue;
+                                }
+                            };
+                            let mut connection = match Connection::new(stream, self.options) {
+                                Ok(connection) => connection,
+                                Err(e) => {
+                                    self.handle_error(e);
+                                    continue;
+                                }
+                            };
+                            connection.set_rpc_handlers(self.rpc_handlers.clone());
+
+                            let client = LogicalClient {
+                                id,
+                                address,
+                                peer_addr,
+                                connected_at: SystemTime::now(),
+                                negotiated: Hello::default(),
+                                auth_timeout: self.auth_timeout,
+                                extensions: Arc::new(Mutex::new(Extensions::new())),
+                                connection,
+                                shared: self.shared.clone(),
+                                #[cfg(feature = "serde")]
+                                codec: MessageCodec::default(),
+                            };
+
+                            let wait = self.overflow_policy == OverflowPolicy::Queue;
+                            match self.shared.slots.acquire(wait, &self.shared.stopping) {
+                                Some(slot) => {
+                                    self.shared.hold(&client);
+                                    s.spawn(move |_| {
+                                        let _slot = slot;
+                                        let address = client.address();
+                                        let mut client = client;
+                                        #[cfg(feature = "websocket")]
+                                        if websocket {
+                                            if let Err(e) = client
+                                                .connection
+                                                .accept_websocket(WEBSOCKET_DETECT_TIMEOUT)
+                                            {
+                                                shared.handle_error(e);
+                                                let _ = client.disconnect();
+                                                shared.unregister(id);
+                                                return;
+                                            }
+                                        }
+                                        let accepted = client.negotiate(hello, hello_timeout)
+                                            && auth_handler
+                                                .as_ref()
+                                                .is_none_or(|auth| client.authenticate(auth));
+                                        if !accepted {
+                                            shared.unregister(id);
+                                            return;
+                                        }
+                                        if let Err(e) = client.connection.dispatch_calls() {
+                                            shared.handle_error(e);
+                                            let _ = client.disconnect();
+                                            shared.unregister(id);
+                                            return;
+                                        }
+                                        shared.register(&client);
+                                        let _registration = RegistrationGuard {
+                                            shared,
+                                            id,
+                                            address,
+                                            disconnect_handler: disconnect_handler.as_ref(),
+                                        };
+                                        handler(client);
+                                    });
+                                }
+                                None => {
+                                    self.refuse(client);
+                                }
                             }
                         }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                            thread::sleep(ACCEPT_POLL_INTERVAL)
+                        }
+                        Err(e) => self.handle_error(Error::Io(e)),
                     }
                 }
-            });
-        }) {
-            self.handle_error(ServerError("Failed to spawn listener thread".to_string()));
+            }
+        })
+        .is_err()
+        {
+            self.handle_error(Error::HandlerPanicked);
+        }
+
+        self.listener = None;
+        self.log(LogLevel::INFO, "Server stopped.");
+    }
+
+    /// Internal function, binds the Unix listener if a path was given to
+    /// [ServerBuilder::unix_path], the TCP listener otherwise.
+    fn bind(&self) -> Result<Listener, Error> {
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_path {
+            #[cfg(feature = "rustls")]
+            if self.tls.is_some() {
+                return Err(Error::Bind {
+                    address: path.display().to_string(),
+                    source: std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "TLS is not supported on Unix sockets",
+                    ),
+                });
+            }
+
+            return bind_unix(path, self.unix_permissions);
+        }
+
+        let address = format!("{}:{}", self.address, self.port);
+        match TcpListener::bind(&address) {
+            Ok(listener) => Ok(Listener::Tcp(listener)),
+            Err(source) => Err(Error::Bind { address, source }),
         }
     }
 
+    /// Internal function, builds the TLS configuration from the identity given to [ServerBuilder::tls].
+    #[cfg(feature = "rustls")]
+    fn tls_config(&self) -> Result<Option<Arc<ServerConfig>>, Error> {
+        let (cert_chain, private_key) = match &self.tls {
+            Some(identity) => identity,
+            None => return Ok(None),
+        };
+
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|config| {
+                config
+                    .with_no_client_auth()
+                    .with_single_cert(cert_chain.clone(), private_key.clone_key())
+            })
+            .map(|config| Some(Arc::new(config)))
+            .map_err(Error::Tls)
+    }
+
+    /// Internal function, used to turn away a client when the server is full.
+    fn refuse(&self, mut client: LogicalClient) {
+        self.log(
+            LogLevel::WARN,
+            &format!("Server is full, refusing {}.", client.address()),
+        );
+
+        if self.overflow_policy != OverflowPolicy::Reject || client.is_shutting_down() {
+            let _ = client.disconnect();
+            return;
+        }
+
+        // The hello is read first: closing with unread data resets the connection, and the client
+        // would see a reset instead of the reason. A thread keeps the accept loop going meanwhile.
+        thread::spawn(move || {
+            let _ = client.connection.read_within(REFUSE_TIMEOUT);
+            let _ = client.send(hello::rejection("server is full"));
+            let _ = client.connection.shutdown(Shutdown::Write);
+        });
+    }
+
     /// Internal function, used to handle errors propagated by the server.
     /// You can also use a custom handler specifing it when building the physical server (see [ServerBuilder::error_handler]).
-    fn handle_error(&self, error: ServerError) {
-        if let Some(handler) = &self.error_handler {
-            handler(error);
-        } else {
-            println!("{}", error);
-        }
+    fn handle_error(&self, error: Error) {
+        self.shared.handle_error(error);
     }
 
     /// Log a message from the physical server.
     pub fn log(&self, level: LogLevel, message: &str) {
-        if let Some(handler) = &self.log_handler {
-            handler(LogStage::SERVER, level, message);
-        } else {
-            println!("[SERVER][{:?}]: {}", level, message);
-        }
+        self.shared.log(level, message);
+    }
+}
+
+/// Wraps an accepted TCP connection in TLS when the server has a TLS configuration.
+/// The handshake itself happens on the handler thread, the first time the client is used.
+#[cfg(feature = "rustls")]
+fn accept_tls(tls: &Option<Arc<ServerConfig>>, stream: Stream) -> Result<Stream, Error> {
+    match (tls, stream) {
+        (Some(config), Stream::Tcp(stream)) => match ServerConnection::new(config.clone()) {
+            Ok(connection) => Ok(Stream::TlsServer(Box::new(StreamOwned::new(
+                connection, stream,
+            )))),
+            Err(e) => Err(Error::Tls(e)),
+        },
+        (Some(_), _) => Err(Error::Tls(rustls::Error::General(
+            "TLS is not supported on Unix sockets".to_string(),
+        ))),
+        (None, stream) => Ok(stream),
     }
 }
 
+/// Returns the `client handler` used when none is set, it keeps the client connected until
+/// it disconnects when `answer_calls` is true, so remote procedure calls can be made.
+fn default_client_handler(answer_calls: bool) -> ClientHandler {
+    Box::new(move |mut c| {
+        println!("{} connected.", c.address());
+        if answer_calls {
+            while c.read().is_ok() {}
+        }
+    })
+}
+
 /// Server builder object.
 /// Can be used to create [Server] objects in a convenient and flexible way.
 /// ```
-/// //e.g.
-/// fn main() {
-///     let server = ServerBuilder::new().address("192.168.1.151").port(4353).build();
-/// }
+/// use bitsock::server::ServerBuilder;
+///
+/// let server = ServerBuilder::new().address("192.168.1.151").port(4353).build();
 /// ```
 pub struct ServerBuilder<'a> {
     address: &'a str,
     port: u16,
-    error_handler: Option<Box<dyn Fn(ServerError) -> () + Send + Sync>>,
-    client_handler: Box<dyn Fn(LogicalClient) -> () + Send + Sync>,
-    log_handler: Option<Box<dyn Fn(LogStage, LogLevel, &str) + Send + Sync>>,
+    #[cfg(unix)]
+    unix_path: Option<PathBuf>,
+    #[cfg(unix)]
+    unix_permissions: Option<u32>,
+    max_connections: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    #[cfg(feature = "websocket")]
+    websocket: bool,
+    options: ConnectionOptions,
+    rpc_handlers: RpcHandlers,
+    #[cfg(feature = "rustls")]
+    tls: Option<TlsIdentity>,
+    hello: Hello,
+    hello_timeout: Duration,
+    auth_timeout: Duration,
+    error_handler: Option<ErrorHandler>,
+    auth_handler: Option<AuthHandler>,
+    client_handler: Option<ClientHandler>,
+    log_handler: Option<LogHandler>,
+    disconnect_handler: Option<DisconnectHandler>,
 }
 
 impl<'a> ServerBuilder<'a> {
@@ -179,82 +1192,290 @@ impl<'a> ServerBuilder<'a> {
         Self {
             address: "0.0.0.0",
             port: 4444,
+            #[cfg(unix)]
+            unix_path: None,
+            #[cfg(unix)]
+            unix_permissions: None,
+            max_connections: None,
+            overflow_policy: OverflowPolicy::Queue,
+            #[cfg(feature = "websocket")]
+            websocket: false,
+            options: ConnectionOptions::default(),
+            rpc_handlers: HashMap::new(),
+            #[cfg(feature = "rustls")]
+            tls: None,
+            hello: Hello::default(),
+            hello_timeout: HELLO_TIMEOUT,
+            auth_timeout: AUTH_TIMEOUT,
             error_handler: None,
-            client_handler: Box::new(|c| println!("{} connected.", c.address())),
+            auth_handler: None,
+            client_handler: None,
             log_handler: None,
+            disconnect_handler: None,
         }
     }
 
     /// Sets the server address.
-    pub fn address(mut self, address: &'a str) -> Self {
+    pub fn address(self, address: &'a str) -> Self {
+        Self { address, ..self }
+    }
+
+    /// Sets the server port.
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Listen on a Unix domain socket at `path` instead of TCP, the address and port are ignored.
+    /// A socket file left behind by a server that did not stop cleanly is removed, and the file is
+    /// removed again once the server stops. The server fails to start if TLS is also configured.
+    #[cfg(unix)]
+    pub fn unix_path<P: AsRef<Path>>(self, path: P) -> Self {
         Self {
-            address: address,
-            port: self.port,
-            error_handler: std::mem::replace(&mut self.error_handler, None),
-            client_handler: self.client_handler,
-            log_handler: std::mem::replace(&mut self.log_handler, None),
+            unix_path: Some(path.as_ref().to_path_buf()),
+            ..self
         }
     }
 
-    /// Sets the server port.
-    pub fn port(mut self, port: u16) -> Self {
+    /// Sets the permissions of the Unix socket file, for example `0o660` to only let the owner and
+    /// its group connect. The socket is created with the process umask otherwise. The socket only
+    /// appears at its path once the permissions are set.
+    #[cfg(unix)]
+    pub fn unix_permissions(self, mode: u32) -> Self {
         Self {
-            address: self.address,
-            port: port,
-            error_handler: std::mem::replace(&mut self.error_handler, None),
-            client_handler: self.client_handler,
-            log_handler: std::mem::replace(&mut self.log_handler, None),
+            unix_permissions: Some(mode),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of clients handled at the same time, unlimited by default.
+    pub fn max_connections(self, max_connections: usize) -> Self {
+        Self {
+            max_connections: Some(max_connections),
+            ..self
+        }
+    }
+
+    /// Sets what happens to new clients when [ServerBuilder::max_connections] is reached,
+    /// [OverflowPolicy::Queue] by default.
+    pub fn overflow_policy(self, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            overflow_policy,
+            ..self
+        }
+    }
+
+    /// Sets how long [LogicalClient::read] waits for a packet before returning [Error::Timeout].
+    /// A frame still arriving when it expires closes the connection with [Error::ConnectionLost].
+    pub fn read_timeout(self, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                read_timeout: Some(timeout),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Sets how long [LogicalClient::send] waits for the packet to be written before returning
+    /// [Error::Timeout].
+    pub fn write_timeout(self, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                write_timeout: Some(timeout),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Enables TCP keepalive on every client connection, probing the client after `time` without traffic.
+    pub fn keepalive(self, time: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                keepalive: Some(time),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Enables the heartbeat: while [LogicalClient::read] waits, a ping is sent to the client after
+    /// `interval` of silence, and the client is dropped after `timeout` of silence. Dropped clients
+    /// are logged and passed to the `error handler` as [Error::Idle].
+    pub fn heartbeat(self, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            options: ConnectionOptions {
+                heartbeat: Some(Heartbeat { interval, timeout }),
+                ..self.options
+            },
+            ..self
+        }
+    }
+
+    /// Accept clients over TLS using the given certificate chain and private key.
+    #[cfg(feature = "rustls")]
+    pub fn tls(
+        self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        private_key: PrivateKeyDer<'static>,
+    ) -> Self {
+        Self {
+            tls: Some((cert_chain, private_key)),
+            ..self
+        }
+    }
+
+    /// Accept WebSocket upgrades next to native clients, each binary message carries one encoded
+    /// [Packet]. WebSocket clients are handed to the same `client handler` as native ones.
+    ///
+    /// A client is recognised by its first bytes, so native clients that stay silent delay their
+    /// `client handler` by up to 200 milliseconds. TLS clients are never upgraded. Like native
+    /// clients, WebSocket clients must start with their [Hello].
+    #[cfg(feature = "websocket")]
+    pub fn websocket(self, enabled: bool) -> Self {
+        Self {
+            websocket: enabled,
+            ..self
+        }
+    }
+
+    /// Sets the application protocol announced in the [Hello], clients speaking another name or
+    /// version are refused.
+    pub fn protocol(self, name: &str, version: u32) -> Self {
+        Self {
+            hello: Hello {
+                protocol: name.to_string(),
+                protocol_version: version,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets the capabilities announced in the [Hello].
+    pub fn capabilities(self, capabilities: Capabilities) -> Self {
+        Self {
+            hello: Hello {
+                capabilities,
+                ..self.hello
+            },
+            ..self
+        }
+    }
+
+    /// Sets how long a client has to send its [Hello] before it is disconnected,
+    /// [HELLO_TIMEOUT] by default.
+    pub fn hello_timeout(self, hello_timeout: Duration) -> Self {
+        Self {
+            hello_timeout,
+            ..self
+        }
+    }
+
+    /// Sets how long each step of the `auth handler` can wait for the client, [AUTH_TIMEOUT] by
+    /// default, see [LogicalClient::auth_timeout].
+    pub fn auth_timeout(self, auth_timeout: Duration) -> Self {
+        Self {
+            auth_timeout,
+            ..self
         }
     }
 
     /// Sets the server `error handler`
-    pub fn error_handler(mut self, handler: Box<dyn Fn(ServerError) -> () + Send + Sync>) -> Self {
+    pub fn error_handler(self, handler: ErrorHandler) -> Self {
         Self {
-            address: self.address,
-            port: self.port,
             error_handler: Some(handler),
-            client_handler: self.client_handler,
-            log_handler: std::mem::replace(&mut self.log_handler, None),
+            ..self
+        }
+    }
+
+    /// Sets the server `auth handler`, run on every new client before the `client handler`, see
+    /// [auth]. Rejected clients are sent the reason and disconnected without reaching the
+    /// `client handler` or the `disconnect handler`. Until accepted, a client is not listed by
+    /// [ServerHandle::clients] and does not receive broadcasts.
+    ///
+    /// The built-in handlers give up on a client that stays silent for the
+    /// [ServerBuilder::auth_timeout], so it cannot hold a connection slot.
+    pub fn auth_handler(self, handler: AuthHandler) -> Self {
+        Self {
+            auth_handler: Some(handler),
+            ..self
         }
     }
 
     /// Sets the server `client handler`
-    pub fn client_handler(
-        mut self,
-        handler: Box<dyn Fn(LogicalClient) -> () + Send + Sync>,
-    ) -> Self {
+    pub fn client_handler(self, handler: ClientHandler) -> Self {
         Self {
-            address: self.address,
-            port: self.port,
-            error_handler: std::mem::replace(&mut self.error_handler, None),
-            client_handler: handler,
-            log_handler: std::mem::replace(&mut self.log_handler, None),
+            client_handler: Some(handler),
+            ..self
         }
     }
 
     /// Sets the server `logger`
-    pub fn log_handler(
-        mut self,
-        handler: Box<dyn Fn(LogStage, LogLevel, &str) + Send + Sync>,
-    ) -> Self {
+    pub fn log_handler(self, handler: LogHandler) -> Self {
         Self {
-            address: self.address,
-            port: self.port,
-            error_handler: std::mem::replace(&mut self.error_handler, None),
-            client_handler: self.client_handler,
             log_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Registers the handler answering the remote procedure calls to a method, see [RpcClient].
+    /// Calls are answered on a thread of their own, whether or not the `client handler` reads.
+    /// When no `client handler` is set, the default one keeps the client connected until it
+    /// disconnects.
+    ///
+    /// [RpcClient]: crate::rpc::RpcClient
+    pub fn rpc_handler(mut self, method: u32, handler: RpcHandler) -> Self {
+        self.rpc_handlers.insert(method, handler);
+        self
+    }
+
+    /// Sets the server `disconnect handler`, called with the address of a client once its
+    /// `client handler` returned and the connection is closed.
+    pub fn disconnect_handler(self, handler: DisconnectHandler) -> Self {
+        Self {
+            disconnect_handler: Some(handler),
+            ..self
         }
     }
 
     /// Build the server object.
-    pub fn build(mut self) -> Server<'a> {
+    pub fn build(self) -> Server<'a> {
+        let client_handler = self
+            .client_handler
+            .unwrap_or_else(|| default_client_handler(!self.rpc_handlers.is_empty()));
+
         Server {
             address: self.address,
             port: self.port,
+            #[cfg(unix)]
+            unix_path: self.unix_path,
+            #[cfg(unix)]
+            unix_permissions: self.unix_permissions,
             listener: None,
-            error_handler: std::mem::replace(&mut self.error_handler, None),
-            client_handler: self.client_handler,
-            log_handler: std::mem::replace(&mut self.log_handler, None),
+            overflow_policy: self.overflow_policy,
+            #[cfg(feature = "websocket")]
+            websocket: self.websocket,
+            options: self.options,
+            rpc_handlers: Arc::new(self.rpc_handlers),
+            shared: Shared::new(self.max_connections, self.error_handler, self.log_handler),
+            #[cfg(feature = "rustls")]
+            tls: self.tls,
+            hello: Hello {
+                auth_required: self.auth_handler.is_some(),
+                ..self.hello
+            },
+            hello_timeout: self.hello_timeout,
+            auth_timeout: self.auth_timeout,
+            auth_handler: self.auth_handler,
+            client_handler,
+            disconnect_handler: self.disconnect_handler,
         }
     }
 }
+
+impl<'a> Default for ServerBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
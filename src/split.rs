@@ -0,0 +1,123 @@
+use std::{net::Shutdown, sync::Arc};
+
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "serde")]
+use crate::message::{MessageCodec, MessageError};
+use crate::{
+    stream::{Connection, Socket, StreamWriter},
+    Error, Packet,
+};
+
+/// Called once when the heartbeat of a [PacketReader] finds the peer idle.
+pub(crate) type IdleHandler = Box<dyn Fn() + Send + Sync>;
+
+/// Reading half of a [Client](crate::client::Client) or [LogicalClient](crate::server::LogicalClient),
+/// obtained with their `split` method. It keeps answering the heartbeat of the peer.
+pub struct PacketReader {
+    connection: Connection,
+    on_idle: IdleHandler,
+    #[cfg(feature = "serde")]
+    codec: MessageCodec,
+}
+
+/// Writing half of a [Client](crate::client::Client) or [LogicalClient](crate::server::LogicalClient),
+/// obtained with their `split` method. Clones write to the same connection, a packet is never
+/// interleaved with another one.
+#[derive(Clone)]
+pub struct PacketWriter {
+    writer: StreamWriter,
+    socket: Arc<Socket>,
+    #[cfg(feature = "serde")]
+    codec: MessageCodec,
+}
+
+/// Splits a connection in its two halves.
+pub(crate) fn split(
+    connection: Connection,
+    on_idle: IdleHandler,
+    #[cfg(feature = "serde")] codec: MessageCodec,
+) -> Result<(PacketReader, PacketWriter), Error> {
+    let writer = PacketWriter {
+        writer: connection.writer().clone(),
+        socket: Arc::new(connection.socket().try_clone()?),
+        #[cfg(feature = "serde")]
+        codec,
+    };
+    let reader = PacketReader {
+        connection,
+        on_idle,
+        #[cfg(feature = "serde")]
+        codec,
+    };
+
+    Ok((reader, writer))
+}
+
+impl PacketReader {
+    /// Listen to a [Packet] from the peer.
+    /// Returns [Error::Disconnected] once the peer closed the connection.
+    ///
+    /// Returns [Error::Timeout] if the read timeout expires, or if the heartbeat timeout expires,
+    /// in which case the connection is closed.
+    pub fn read(&mut self) -> Result<Packet, Error> {
+        let result = self.connection.read();
+
+        if self.connection.is_idle() {
+            let _ = self.disconnect();
+            (self.on_idle)();
+        }
+
+        result
+    }
+
+    /// Close the connection with the peer, for both halves.
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.connection.shutdown(Shutdown::Both)?;
+
+        Ok(())
+    }
+}
+
+impl PacketWriter {
+    /// Send a [Packet] to the peer.
+    pub fn send(&self, packet: Packet) -> Result<usize, Error> {
+        self.writer.send(&packet)
+    }
+
+    /// Close the connection with the peer, for both halves.
+    pub fn disconnect(&self) -> Result<(), Error> {
+        self.socket.shutdown(Shutdown::Both)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl PacketReader {
+    /// Sets the codec used by [PacketReader::read_message].
+    pub fn set_message_codec(&mut self, codec: MessageCodec) {
+        self.codec = codec;
+    }
+
+    /// Listen to a message from the peer and deserialize it.
+    pub fn read_message<T: DeserializeOwned>(&mut self) -> Result<T, MessageError> {
+        let packet = self.read().map_err(MessageError::Connection)?;
+        self.codec.from_packet(packet)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl PacketWriter {
+    /// Sets the codec used by [PacketWriter::send_message].
+    pub fn set_message_codec(&mut self, codec: MessageCodec) {
+        self.codec = codec;
+    }
+
+    /// Serialize a message and send it to the peer.
+    pub fn send_message<T: Serialize>(&self, message: &T) -> Result<usize, MessageError> {
+        self.send(self.codec.to_packet(message)?)
+            .map_err(MessageError::Connection)
+    }
+}
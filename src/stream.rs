@@ -0,0 +1,719 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "rustls")]
+use rustls::{ClientConnection, ConnectionCommon, ServerConnection, SideData, StreamOwned};
+use socket2::{SockRef, TcpKeepalive};
+
+#[cfg(feature = "websocket")]
+use crate::websocket::{self, WebSocketStream};
+use crate::{
+    rpc::{self, RpcHandlers},
+    server::ConnectionStats,
+    Error, Packet, FRAME_HEADER_SIZE, MAX_FRAME_SIZE, PING_ID, PONG_ID, RPC_REQUEST_ID,
+};
+
+/// Socket under a [Stream], used to read, shut down and set timeouts without going through TLS.
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// Returns a new handle to the same socket.
+    pub(crate) fn try_clone(&self) -> Result<Self, std::io::Error> {
+        match self {
+            Socket::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.try_clone().map(Socket::Unix),
+        }
+    }
+
+    /// Shuts down the read, write, or both halves of the socket.
+    pub(crate) fn shutdown(&self, how: Shutdown) -> Result<(), std::io::Error> {
+        match self {
+            Socket::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        match self {
+            Socket::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), std::io::Error> {
+        match self {
+            Socket::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+/// Transport used by [Client](crate::client::Client) and
+/// [LogicalClient](crate::server::LogicalClient), packets are framed the same way on all of them.
+pub(crate) enum Stream {
+    /// Plain TCP connection.
+    Tcp(TcpStream),
+    /// Unix domain socket connection.
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// TLS connection accepted by a server.
+    #[cfg(feature = "rustls")]
+    TlsServer(Box<StreamOwned<ServerConnection, TcpStream>>),
+    /// TLS connection opened by a client.
+    #[cfg(feature = "rustls")]
+    TlsClient(Box<StreamOwned<ClientConnection, TcpStream>>),
+    /// WebSocket connection, each binary message carries a packet.
+    #[cfg(feature = "websocket")]
+    WebSocket(Box<WebSocketStream>),
+}
+
+impl Stream {
+    /// Returns a new handle to the socket under the transport.
+    pub(crate) fn socket(&self) -> Result<Socket, std::io::Error> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Socket::Unix),
+            #[cfg(feature = "rustls")]
+            Stream::TlsServer(stream) => stream.get_ref().try_clone().map(Socket::Tcp),
+            #[cfg(feature = "rustls")]
+            Stream::TlsClient(stream) => stream.get_ref().try_clone().map(Socket::Tcp),
+            #[cfg(feature = "websocket")]
+            Stream::WebSocket(stream) => stream.tcp().try_clone().map(Socket::Tcp),
+        }
+    }
+
+    /// Splits the transport into a reader and a writer that can be used from different threads.
+    pub(crate) fn split(self) -> Result<(StreamReader, StreamWriter), std::io::Error> {
+        let socket = self.socket()?;
+        #[cfg(any(feature = "rustls", feature = "websocket"))]
+        let locked = self.is_locked();
+        let stream = Arc::new(Mutex::new(self));
+        let counters = Arc::new(Counters::default());
+        let max_frame_size = Arc::new(AtomicUsize::new(MAX_FRAME_SIZE));
+
+        let reader = StreamReader {
+            socket,
+            #[cfg(any(feature = "rustls", feature = "websocket"))]
+            locked: locked.then(|| stream.clone()),
+            counters: counters.clone(),
+            max_frame_size: max_frame_size.clone(),
+        };
+
+        let writer = StreamWriter {
+            stream,
+            counters,
+            max_frame_size,
+        };
+
+        Ok((reader, writer))
+    }
+
+    /// Returns true if the transport decodes what it reads from the socket, so reading needs the
+    /// lock shared with the writer.
+    #[cfg(any(feature = "rustls", feature = "websocket"))]
+    fn is_locked(&self) -> bool {
+        match self {
+            Stream::Tcp(_) => false,
+            #[cfg(unix)]
+            Stream::Unix(_) => false,
+            #[cfg(feature = "rustls")]
+            Stream::TlsServer(_) | Stream::TlsClient(_) => true,
+            #[cfg(feature = "websocket")]
+            Stream::WebSocket(_) => true,
+        }
+    }
+
+    /// Returns the plaintext of the TLS records or WebSocket messages received so far, first
+    /// reading what is waiting on the socket if `fetch` is true. Returns [ErrorKind::WouldBlock]
+    /// when there is no plaintext yet.
+    #[cfg(any(feature = "rustls", feature = "websocket"))]
+    fn read_plaintext(&mut self, fetch: bool, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+            #[cfg(feature = "rustls")]
+            Stream::TlsServer(stream) => {
+                read_plaintext(&mut stream.conn, &mut stream.sock, fetch, buf)
+            }
+            #[cfg(feature = "rustls")]
+            Stream::TlsClient(stream) => {
+                read_plaintext(&mut stream.conn, &mut stream.sock, fetch, buf)
+            }
+            #[cfg(feature = "websocket")]
+            Stream::WebSocket(stream) => stream.read_frames(fetch, buf),
+        }
+    }
+}
+
+/// Reads the TLS records waiting on the socket if `fetch` is true, answering handshake messages,
+/// then returns the plaintext received so far.
+#[cfg(feature = "rustls")]
+fn read_plaintext<S: SideData>(
+    connection: &mut ConnectionCommon<S>,
+    sock: &mut TcpStream,
+    fetch: bool,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    if fetch {
+        connection.read_tls(sock)?;
+        connection
+            .process_new_packets()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+        while connection.wants_write() {
+            connection.write_tls(sock)?;
+        }
+    }
+
+    connection.reader().read(buf)
+}
+
+/// Reading half of a [Stream], owned by the thread reading packets.
+pub(crate) struct StreamReader {
+    socket: Socket,
+    #[cfg(any(feature = "rustls", feature = "websocket"))]
+    locked: Option<Arc<Mutex<Stream>>>,
+    counters: Arc<Counters>,
+    max_frame_size: Arc<AtomicUsize>,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.read_bytes(buf)?;
+        self.counters
+            .bytes_received
+            .fetch_add(size as u64, Ordering::Relaxed);
+
+        Ok(size)
+    }
+}
+
+impl StreamReader {
+    /// Returns a new reader of the same transport, only one of them should be read at a time.
+    fn try_clone(&self) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            #[cfg(any(feature = "rustls", feature = "websocket"))]
+            locked: self.locked.clone(),
+            counters: self.counters.clone(),
+            max_frame_size: self.max_frame_size.clone(),
+        })
+    }
+
+    /// Reads the plaintext of the transport.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(any(feature = "rustls", feature = "websocket"))]
+        if let Some(stream) = &self.locked {
+            let mut fetch = false;
+            loop {
+                match stream.lock().unwrap().read_plaintext(fetch, buf) {
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+
+                // Wait for the next records without holding the lock, so writers are not blocked.
+                // TLS and WebSocket are only used over TCP.
+                if let Socket::Tcp(tcp) = &self.socket {
+                    tcp.peek(&mut [0])?;
+                }
+                fetch = true;
+            }
+        }
+
+        self.socket.read(buf)
+    }
+}
+
+/// Traffic of a connection, shared by its reader and every clone of its writer.
+#[derive(Default)]
+pub(crate) struct Counters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_sent: AtomicU64,
+    packets_received: AtomicU64,
+}
+
+impl Counters {
+    /// Returns the traffic counted so far.
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writing half of a [Stream], shared by everything sending packets to the same peer.
+#[derive(Clone)]
+pub(crate) struct StreamWriter {
+    stream: Arc<Mutex<Stream>>,
+    counters: Arc<Counters>,
+    max_frame_size: Arc<AtomicUsize>,
+}
+
+impl StreamWriter {
+    /// Writes a packet as a single frame, frames written from different threads never interleave.
+    pub(crate) fn send(&self, packet: &Packet) -> Result<usize, Error> {
+        let frame = packet.checked_frame()?;
+        self.write_frame(&frame)?;
+
+        Ok(frame.len())
+    }
+
+    /// Writes a frame built by [Packet::checked_frame], failing if it is bigger than the maximum
+    /// frame size agreed with the peer.
+    pub(crate) fn write_frame(&self, frame: &[u8]) -> Result<(), Error> {
+        self.write(frame)?;
+        self.counters.packets_sent.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Sends a heartbeat packet, which is left out of the packet counters.
+    fn send_heartbeat(&self, id: u32) -> Result<(), Error> {
+        self.write(&Packet::Identified(id, Vec::new()).checked_frame()?)
+    }
+
+    fn write(&self, frame: &[u8]) -> Result<(), Error> {
+        let size = frame.len() - FRAME_HEADER_SIZE;
+        let max = self.max_frame_size.load(Ordering::Relaxed);
+        if size > max {
+            return Err(Error::FrameTooLarge { size, max });
+        }
+
+        let mut stream = self.stream.lock().unwrap();
+        stream.write_all(frame)?;
+        stream.flush()?;
+
+        self.counters
+            .bytes_sent
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Returns the traffic counters of the connection.
+    pub(crate) fn counters(&self) -> &Arc<Counters> {
+        &self.counters
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+            #[cfg(feature = "rustls")]
+            Stream::TlsServer(stream) => stream.write(buf),
+            #[cfg(feature = "rustls")]
+            Stream::TlsClient(stream) => stream.write(buf),
+            #[cfg(feature = "websocket")]
+            Stream::WebSocket(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+            #[cfg(feature = "rustls")]
+            Stream::TlsServer(stream) => stream.flush(),
+            #[cfg(feature = "rustls")]
+            Stream::TlsClient(stream) => stream.flush(),
+            #[cfg(feature = "websocket")]
+            Stream::WebSocket(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Application level heartbeat: a ping is sent when the peer was silent for `interval`, and the
+/// peer is considered gone when it was silent for `timeout`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Heartbeat {
+    pub(crate) interval: Duration,
+    pub(crate) timeout: Duration,
+}
+
+/// Timeouts, keepalive and heartbeat of a connection, set with the client and server builders.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ConnectionOptions {
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) heartbeat: Option<Heartbeat>,
+}
+
+impl ConnectionOptions {
+    /// Applies the socket level options to a socket, keepalive only applies to TCP.
+    fn apply(&self, socket: &Socket) -> Result<(), std::io::Error> {
+        socket.set_write_timeout(self.write_timeout)?;
+
+        if let (Some(time), Socket::Tcp(stream)) = (self.keepalive, socket) {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream sending and reading packets, answering the heartbeat of the peer and sending its own.
+pub(crate) struct Connection {
+    reader: StreamReader,
+    writer: StreamWriter,
+    options: ConnectionOptions,
+    rpc_handlers: Option<Arc<RpcHandlers>>,
+    last_received: Instant,
+    last_ping: Instant,
+    dispatched: Option<Dispatched>,
+}
+
+/// Packets read by the thread answering remote procedure calls, see [Connection::dispatch_calls].
+struct Dispatched {
+    packets: Receiver<Result<Packet, Error>>,
+    idle: Arc<AtomicBool>,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: Stream, options: ConnectionOptions) -> Result<Self, Error> {
+        let (reader, writer) = stream.split()?;
+        options.apply(&reader.socket)?;
+
+        let now = Instant::now();
+        Ok(Self {
+            reader,
+            writer,
+            options,
+            rpc_handlers: None,
+            last_received: now,
+            last_ping: now,
+            dispatched: None,
+        })
+    }
+
+    /// Sets the handlers answering the remote procedure calls of the peer, calls to methods without
+    /// a handler are answered with an error.
+    pub(crate) fn set_rpc_handlers(&mut self, handlers: Arc<RpcHandlers>) {
+        self.rpc_handlers = Some(handlers);
+    }
+
+    /// Starts reading on a thread of its own, so the remote procedure calls of the peer are
+    /// answered even while nothing is read. Does nothing without handlers.
+    ///
+    /// The other packets are handed over to [Connection::read], the heartbeat keeps being answered
+    /// and the connection is closed once the peer is idle.
+    pub(crate) fn dispatch_calls(&mut self) -> Result<(), Error> {
+        if self.rpc_handlers.as_ref().is_none_or(|h| h.is_empty()) || self.dispatched.is_some() {
+            return Ok(());
+        }
+
+        let mut dispatcher = Connection {
+            reader: self.reader.try_clone()?,
+            writer: self.writer.clone(),
+            options: ConnectionOptions {
+                read_timeout: None,
+                ..self.options
+            },
+            rpc_handlers: self.rpc_handlers.clone(),
+            last_received: self.last_received,
+            last_ping: self.last_ping,
+            dispatched: None,
+        };
+        let (sender, packets) = mpsc::channel();
+        let idle = Arc::new(AtomicBool::new(false));
+
+        let flagged = idle.clone();
+        thread::spawn(move || loop {
+            let result = dispatcher.read();
+            let failed = result.is_err();
+            if failed && dispatcher.is_idle() {
+                flagged.store(true, Ordering::Relaxed);
+                let _ = dispatcher.shutdown(Shutdown::Both);
+            }
+
+            if sender.send(result).is_err() || failed {
+                break;
+            }
+        });
+
+        self.dispatched = Some(Dispatched { packets, idle });
+
+        Ok(())
+    }
+
+    /// Shuts down the read, write, or both halves of the connection.
+    pub(crate) fn shutdown(&self, how: Shutdown) -> Result<(), std::io::Error> {
+        self.reader.socket.shutdown(how)
+    }
+
+    /// Upgrades a plain TCP connection to WebSocket if the peer starts with an HTTP request, native
+    /// peers start with a version byte instead. Peers silent for `wait` are taken as native.
+    #[cfg(feature = "websocket")]
+    pub(crate) fn accept_websocket(&mut self, wait: Duration) -> Result<(), Error> {
+        let tcp = match (&self.reader.socket, &self.reader.locked) {
+            (Socket::Tcp(tcp), None) => tcp,
+            _ => return Ok(()),
+        };
+
+        tcp.set_read_timeout(Some(wait))?;
+        let mut first = [0];
+        let upgrade = match tcp.peek(&mut first) {
+            Ok(1) => first[0] == b'G',
+            Ok(_) => false,
+            Err(e) if is_timeout(&e) => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        if upgrade {
+            tcp.set_read_timeout(self.options.read_timeout)?;
+            let stream = websocket::accept(tcp.try_clone()?)?;
+            let mut locked = self.writer.stream.lock().unwrap();
+            *locked = Stream::WebSocket(Box::new(stream));
+            self.reader.locked = Some(self.writer.stream.clone());
+        }
+        tcp.set_read_timeout(None)?;
+
+        Ok(())
+    }
+
+    /// Returns true if the transport is TLS.
+    pub(crate) fn is_encrypted(&self) -> bool {
+        #[cfg(feature = "rustls")]
+        if let Stream::TlsServer(_) | Stream::TlsClient(_) = *self.writer.stream.lock().unwrap() {
+            return true;
+        }
+
+        false
+    }
+
+    /// Sets the maximum frame size agreed with the peer, bigger packets are neither sent nor read.
+    pub(crate) fn set_max_frame_size(&self, size: usize) {
+        self.writer.max_frame_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Returns the traffic of the connection.
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        self.writer.counters.stats()
+    }
+
+    /// Returns the socket under the transport.
+    pub(crate) fn socket(&self) -> &Socket {
+        &self.reader.socket
+    }
+
+    /// Returns the writing half of the connection.
+    pub(crate) fn writer(&self) -> &StreamWriter {
+        &self.writer
+    }
+
+    /// Writes a packet as a single frame.
+    pub(crate) fn send(&mut self, packet: &Packet) -> Result<usize, Error> {
+        self.writer.send(packet)
+    }
+
+    /// Reads the next packet that is not a heartbeat or a remote procedure call.
+    ///
+    /// Returns [Error::Timeout] once the read timeout expires or the peer stayed silent for longer
+    /// than the heartbeat timeout, see [Connection::is_idle]. If that happens in the middle of a
+    /// frame, the connection is closed and [Error::ConnectionLost] is returned instead.
+    pub(crate) fn read(&mut self) -> Result<Packet, Error> {
+        if let Some(dispatched) = &self.dispatched {
+            let received = match self.options.read_timeout {
+                Some(timeout) => dispatched.packets.recv_timeout(timeout),
+                None => dispatched
+                    .packets
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            return match received {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+                Err(RecvTimeoutError::Disconnected) => Err(Error::Disconnected),
+            };
+        }
+
+        let read_deadline = self.options.read_timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let heartbeat = self.options.heartbeat;
+            let ping_at = heartbeat.map(|h| self.last_received.max(self.last_ping) + h.interval);
+            let deadline = earliest(
+                read_deadline,
+                heartbeat.map(|h| self.last_received + h.timeout),
+            );
+
+            if let Some(wake) = earliest(deadline, ping_at) {
+                set_read_timeout(&self.reader.socket, wake)?;
+            }
+
+            let max_frame_size = self.reader.max_frame_size.load(Ordering::Relaxed);
+            let mut reader = FrameReader {
+                stream: &mut self.reader,
+                started: false,
+                deadline,
+            };
+            let result = Packet::read_limited(&mut reader, max_frame_size);
+            let cut = reader.started;
+            match result {
+                Ok(Packet::Identified(PING_ID, _)) => {
+                    self.last_received = Instant::now();
+                    self.writer.send_heartbeat(PONG_ID)?;
+                }
+                Ok(Packet::Identified(PONG_ID, _)) => self.last_received = Instant::now(),
+                Ok(packet) => {
+                    self.last_received = Instant::now();
+                    self.reader
+                        .counters
+                        .packets_received
+                        .fetch_add(1, Ordering::Relaxed);
+
+                    match packet {
+                        Packet::Identified(RPC_REQUEST_ID, body) => {
+                            self.send(&rpc::answer(self.rpc_handlers.as_deref(), &body))?;
+                        }
+                        packet => return Ok(packet),
+                    }
+                }
+                Err(Error::Timeout) if cut => {
+                    // The rest of the frame would be read as the start of the next one.
+                    let _ = self.shutdown(Shutdown::Both);
+                    return Err(Error::ConnectionLost(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        "the read timed out in the middle of a frame",
+                    )));
+                }
+                Err(Error::Timeout) => {
+                    let now = Instant::now();
+                    if deadline.is_some_and(|deadline| now >= deadline) {
+                        return Err(Error::Timeout);
+                    }
+                    if ping_at.is_some_and(|ping_at| now >= ping_at) {
+                        self.writer.send_heartbeat(PING_ID)?;
+                        self.last_ping = now;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads the next packet like [Connection::read], giving up after `timeout` instead of the
+    /// read timeout.
+    pub(crate) fn read_within(&mut self, timeout: Duration) -> Result<Packet, Error> {
+        let read_timeout = self.options.read_timeout.replace(timeout);
+        let result = self.read();
+        self.options.read_timeout = read_timeout;
+        if self.dispatched.is_some() {
+            return result;
+        }
+
+        // Reads only set a socket timeout when they have a deadline, so it must not stay behind.
+        self.reader.socket.set_read_timeout(None)?;
+
+        result
+    }
+
+    /// Returns true if the peer stayed silent for longer than the heartbeat timeout.
+    pub(crate) fn is_idle(&self) -> bool {
+        if let Some(dispatched) = &self.dispatched {
+            return dispatched.idle.load(Ordering::Relaxed);
+        }
+
+        self.options
+            .heartbeat
+            .is_some_and(|h| self.last_received.elapsed() >= h.timeout)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Stops the thread reading for the connection, writers may still use it.
+        if self.dispatched.is_some() {
+            let _ = self.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+/// Reader that keeps waiting through socket timeouts once a frame started arriving, so the
+/// heartbeat never cuts a frame in half. Gives up at the deadline.
+struct FrameReader<'s> {
+    stream: &'s mut StreamReader,
+    started: bool,
+    deadline: Option<Instant>,
+}
+
+impl Read for FrameReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match self.stream.read(buf) {
+                Err(e) if self.started && is_timeout(&e) => match self.deadline {
+                    Some(deadline) if Instant::now() < deadline => {
+                        set_read_timeout(&self.stream.socket, deadline)?
+                    }
+                    _ => return Err(e),
+                },
+                result => {
+                    self.started |= matches!(result, Ok(n) if n > 0);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the earliest of two optional instants.
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Makes the next read on the stream give up at the given instant.
+fn set_read_timeout(socket: &Socket, until: Instant) -> Result<(), std::io::Error> {
+    // A zero timeout would make reads block forever.
+    let timeout = until
+        .saturating_duration_since(Instant::now())
+        .max(Duration::from_millis(1));
+
+    socket.set_read_timeout(Some(timeout))
+}
+
+fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
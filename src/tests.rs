@@ -1,4 +1,24 @@
-use crate::server::ServerBuilder;
+use std::{
+    io::Cursor,
+    net::UdpSocket,
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    auth,
+    client::{Client, ClientBuilder},
+    hello::{Capabilities, Hello},
+    reconnect::{ConnectionState, ReconnectingClientBuilder},
+    reliable::{Channel, DatagramSocket, ReliableConnection},
+    rpc::{RpcClient, RpcError},
+    server::{LogHandler, LogicalClient, OverflowPolicy, ServerBuilder, ServerHandle},
+    split::{PacketReader, PacketWriter},
+    udp::{UdpClient, UdpServerBuilder},
+    Error, LogLevel, LogStage, Packet, PacketDecodeError, MAX_FRAME_SIZE, VERSION_MARKER,
+    WIRE_VERSION,
+};
 
 #[test]
 fn check_server_builder() {
@@ -9,3 +29,1510 @@ fn check_server_builder() {
     assert_eq!(server.port, 8580);
     assert_eq!(server.address, "192.168.1.84");
 }
+
+#[test]
+fn check_frames_are_read_one_at_a_time() {
+    let mut stream = Vec::new();
+    Packet::I32(5).write_to(&mut stream).unwrap();
+    Packet::U64(42).write_to(&mut stream).unwrap();
+
+    let mut reader = Cursor::new(stream);
+    assert!(matches!(Packet::read_from(&mut reader), Ok(Packet::I32(5))));
+    assert!(matches!(
+        Packet::read_from(&mut reader),
+        Ok(Packet::U64(42))
+    ));
+    assert!(matches!(
+        Packet::read_from(&mut reader),
+        Err(Error::Disconnected)
+    ));
+}
+
+#[test]
+fn check_oversized_frame_is_rejected() {
+    let mut header = vec![VERSION_MARKER | WIRE_VERSION];
+    header.extend_from_slice(&((MAX_FRAME_SIZE + 1) as u32).to_le_bytes());
+    let mut reader = Cursor::new(header);
+
+    assert!(matches!(
+        Packet::read_from(&mut reader),
+        Err(Error::FrameTooLarge { .. })
+    ));
+}
+
+#[test]
+fn check_every_packet_round_trips() {
+    let packets = vec![
+        Packet::Invalid,
+        Packet::Bytes(vec![0, 1, 2, 255]),
+        Packet::String("Hello There!".to_string()),
+        Packet::I8(-8),
+        Packet::I16(-16),
+        Packet::I32(-32),
+        Packet::I64(-64),
+        Packet::F32(3.5),
+        Packet::F64(-7.25),
+        Packet::U8(8),
+        Packet::U16(16),
+        Packet::U32(32),
+        Packet::U64(64),
+        Packet::Identified(7, vec![4, 5, 6]),
+    ];
+
+    for packet in packets {
+        assert_eq!(Packet::decode(packet.encode()).unwrap(), packet);
+
+        let mut reader = Cursor::new(packet.frame());
+        assert_eq!(Packet::read_from(&mut reader).unwrap(), packet);
+    }
+}
+
+#[test]
+fn check_large_packets_arrive_whole() {
+    let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+
+    let mut stream = Vec::new();
+    Packet::Bytes(data.clone()).write_to(&mut stream).unwrap();
+    Packet::String("x".repeat(5000))
+        .write_to(&mut stream)
+        .unwrap();
+
+    let mut reader = Cursor::new(stream);
+    assert_eq!(Packet::read_from(&mut reader).unwrap(), Packet::Bytes(data));
+    assert_eq!(
+        Packet::read_from(&mut reader).unwrap(),
+        Packet::String("x".repeat(5000))
+    );
+}
+
+#[test]
+fn check_legacy_and_malformed_packets_are_rejected() {
+    // Unversioned I32 packet as written by older peers.
+    let legacy = vec![5, 5, 0, 0, 0];
+    assert!(matches!(
+        Packet::decode(legacy.clone()),
+        Err(Error::Decode {
+            tag: None,
+            offset: 0,
+            error: PacketDecodeError::LegacyFormat
+        })
+    ));
+    assert!(matches!(
+        Packet::read_from(&mut Cursor::new(legacy)),
+        Err(Error::Decode {
+            error: PacketDecodeError::LegacyFormat,
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        Packet::decode(vec![VERSION_MARKER | 2, 5]),
+        Err(Error::Decode {
+            error: PacketDecodeError::UnsupportedVersion(2),
+            ..
+        })
+    ));
+    assert!(matches!(
+        Packet::decode(vec![VERSION_MARKER | WIRE_VERSION, 5, 1, 0]),
+        Err(Error::Decode {
+            tag: Some(5),
+            offset: 4,
+            error: PacketDecodeError::InvalidLength(2)
+        })
+    ));
+    assert!(matches!(
+        Packet::decode(vec![VERSION_MARKER | WIRE_VERSION, 9, 1, 0]),
+        Err(Error::Decode {
+            tag: Some(9),
+            offset: 3,
+            error: PacketDecodeError::InvalidLength(2)
+        })
+    ));
+    assert!(matches!(
+        Packet::decode(vec![VERSION_MARKER | WIRE_VERSION, 2, b'o', b'k', 0xff]),
+        Err(Error::Decode {
+            tag: Some(2),
+            offset: 4,
+            error: PacketDecodeError::InvalidUtf8
+        })
+    ));
+    assert!(matches!(
+        Packet::decode(vec![VERSION_MARKER | WIRE_VERSION, 200]),
+        Err(Error::Decode {
+            tag: Some(200),
+            offset: 1,
+            error: PacketDecodeError::UnknownTag(200)
+        })
+    ));
+}
+
+#[test]
+fn check_errors_chain_their_source() {
+    use std::error::Error as _;
+
+    let error = Client::connect("127.0.0.1", 1).err().unwrap();
+    assert!(matches!(error, Error::Connect { .. }));
+    assert!(error.source().is_some());
+    // Each error of the chain is printed once.
+    assert_eq!(error.to_string(), "failed to connect to 127.0.0.1:1");
+
+    let reset = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+    assert!(matches!(reset, Error::ConnectionLost(_)));
+    assert!(reset.source().is_some());
+
+    let server = TestServer::echo(ServerBuilder::new());
+
+    // A second server on the same port fails to bind.
+    let (sender, errors) = mpsc::channel();
+    ServerBuilder::new()
+        .address("127.0.0.1")
+        .port(server.port)
+        .error_handler(Box::new(move |e| sender.send(e).unwrap()))
+        .log_handler(quiet())
+        .build()
+        .run();
+    assert!(matches!(errors.recv().unwrap(), Error::Bind { .. }));
+}
+
+/// Log handler silencing the servers and clients of the tests.
+fn quiet() -> LogHandler {
+    Box::new(|_, _, _| ())
+}
+
+/// Client handler echoing every packet back to the client.
+fn echo(mut client: LogicalClient) {
+    while let Ok(packet) = client.read() {
+        if client.send(packet).is_err() {
+            break;
+        }
+    }
+}
+
+/// Returns a port nothing listens on.
+fn unused_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Returns two UDP sockets of the loopback interface connected to each other.
+fn udp_pair() -> (UdpSocket, UdpSocket) {
+    let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+    first.connect(second.local_addr().unwrap()).unwrap();
+    second.connect(first.local_addr().unwrap()).unwrap();
+
+    (first, second)
+}
+
+/// Server running on another thread, bound to a free port of the loopback interface.
+/// The server is shut down when dropped.
+struct TestServer {
+    handle: ServerHandle,
+    port: u16,
+    running: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Bind the server configured by `builder`, then run it on another thread.
+    fn start(builder: ServerBuilder<'static>) -> Self {
+        let mut server = builder
+            .address("127.0.0.1")
+            .port(0)
+            .log_handler(quiet())
+            .build();
+        server.listen().unwrap();
+        // Unix sockets have no port.
+        let port = server.local_addr().map_or(0, |address| address.port());
+
+        Self {
+            handle: server.handle(),
+            port,
+            running: Some(thread::spawn(move || server.run())),
+        }
+    }
+
+    /// Start a server echoing every packet back to its clients.
+    fn echo(builder: ServerBuilder<'static>) -> Self {
+        Self::start(builder.client_handler(Box::new(echo)))
+    }
+
+    /// Returns a builder for the clients of the server.
+    fn client(&self) -> ClientBuilder {
+        ClientBuilder::new().port(self.port).log_handler(quiet())
+    }
+
+    /// Connect a client to the server.
+    fn connect(&self) -> Client {
+        self.client().connect().unwrap()
+    }
+
+    /// Shut the server down, returns whether its handlers returned within `timeout`.
+    fn shutdown(mut self, timeout: Duration) -> bool {
+        let finished = self.handle.shutdown(timeout);
+        self.running.take().unwrap().join().unwrap();
+
+        finished
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(running) = self.running.take() {
+            self.handle.shutdown(Duration::from_secs(1));
+            let _ = running.join();
+        }
+    }
+}
+
+#[test]
+fn check_clients_are_handled_concurrently() {
+    let server = TestServer::echo(ServerBuilder::new());
+
+    // The first client stays idle, its handler blocks waiting for a packet.
+    let _idle = server.connect();
+
+    let mut client = server.connect();
+    client.send(Packet::I32(5)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::I32(5));
+}
+
+#[test]
+fn check_overflow_policy_rejects_clients() {
+    let server = TestServer::echo(
+        ServerBuilder::new()
+            .max_connections(1)
+            .overflow_policy(OverflowPolicy::Reject),
+    );
+
+    let mut first = server.connect();
+    first.send(Packet::U8(1)).unwrap();
+    assert_eq!(first.read().unwrap(), Packet::U8(1));
+
+    // The reason always arrives, the connection is not reset under it.
+    for _ in 0..5 {
+        let second = server.client().connect();
+        assert!(matches!(second, Err(Error::Handshake(reason)) if reason == "server is full"));
+    }
+}
+
+#[test]
+fn check_server_shuts_down_gracefully() {
+    let server = TestServer::echo(ServerBuilder::new());
+    let (handle, port) = (server.handle.clone(), server.port);
+
+    let mut client = server.connect();
+    client.send(Packet::U16(3)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U16(3));
+
+    assert!(server.shutdown(Duration::from_secs(2)));
+
+    assert!(handle.is_shutting_down());
+    assert!(Client::connect("127.0.0.1", port).is_err());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn check_async_server_and_client() {
+    use crate::asynchronous::{AsyncClient, AsyncServerBuilder};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let server = AsyncServerBuilder::new()
+            .address("127.0.0.1")
+            .port(0)
+            .protocol("chat", 1)
+            .client_handler(|mut c| async move {
+                // Pings are answered by the reads on both sides, so they never reach the handlers.
+                let _ = c.send(Packet::Identified(crate::PING_ID, Vec::new())).await;
+                while let Ok(packet) = c.read().await {
+                    if c.send(packet).await.is_err() {
+                        break;
+                    }
+                }
+            })
+            .log_handler(quiet())
+            .build();
+        server.listen().await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        let handle = server.handle();
+        let running = tokio::spawn(async move { server.run().await });
+
+        let hello = Hello {
+            protocol: "chat".to_string(),
+            protocol_version: 1,
+            ..Hello::default()
+        };
+        let mut client = AsyncClient::connect_with("127.0.0.1", port, hello.clone())
+            .await
+            .unwrap();
+        assert_eq!(client.negotiated().protocol, "chat");
+
+        let data = vec![7; 10_000];
+        client.send(Packet::Bytes(data.clone())).await.unwrap();
+        client.send(Packet::I64(-1)).await.unwrap();
+        assert_eq!(client.read().await.unwrap(), Packet::Bytes(data));
+        assert_eq!(client.read().await.unwrap(), Packet::I64(-1));
+
+        // A client speaking another protocol is refused before the handler sees it.
+        assert!(matches!(
+            AsyncClient::connect("127.0.0.1", port).await,
+            Err(Error::Handshake(_))
+        ));
+
+        // Shutting down ends the reads of the handlers, which close their clients.
+        handle.shutdown(Duration::from_secs(1));
+        running.await.unwrap();
+        assert!(handle.is_shutting_down());
+        assert!(matches!(client.read().await, Err(Error::Disconnected)));
+        assert!(AsyncClient::connect_with("127.0.0.1", port, hello)
+            .await
+            .is_err());
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn check_typed_messages() {
+    use crate::message::MessageCodec;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Move {
+        player: u32,
+        x: f32,
+        y: f32,
+    }
+
+    let server = TestServer::start(ServerBuilder::new().client_handler(Box::new(|mut c| {
+        c.set_message_codec(MessageCodec::Json);
+        while let Ok(message) = c.read_message::<Move>() {
+            let _ = c.send_message(&Move {
+                player: message.player + 1,
+                ..message
+            });
+        }
+    })));
+
+    let mut client = server.connect();
+    client.set_message_codec(MessageCodec::Json);
+    client
+        .send_message(&Move {
+            player: 1,
+            x: 0.5,
+            y: -2.0,
+        })
+        .unwrap();
+    assert_eq!(
+        client.read_message::<Move>().unwrap(),
+        Move {
+            player: 2,
+            x: 0.5,
+            y: -2.0
+        }
+    );
+
+    for codec in [
+        MessageCodec::Bincode,
+        MessageCodec::Json,
+        MessageCodec::MessagePack,
+    ] {
+        let packet = codec.to_packet(&vec!["a", "b"]).unwrap();
+        assert_eq!(
+            codec.from_packet::<Vec<String>>(packet).unwrap(),
+            vec!["a", "b"]
+        );
+    }
+
+    // The codec error is kept as the source, also once converted into an Error.
+    let error = MessageCodec::Json
+        .decode::<Move>(b"{")
+        .map_err(Error::from)
+        .unwrap_err();
+    assert!(matches!(&error, Error::Message(_)));
+    let source = std::error::Error::source(&error).unwrap();
+    assert!(source.is::<serde_json::Error>());
+    // The codec error is not repeated in the message.
+    assert_eq!(error.to_string(), "failed to decode message");
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn check_derived_protocol() {
+    use crate::{
+        protocol::{Protocol, ProtocolError},
+        BitsockProtocol,
+    };
+
+    #[derive(BitsockProtocol, Debug, PartialEq)]
+    enum Chat {
+        Join(String),
+        Leave,
+        #[bitsock(id = 10)]
+        Message(String),
+        Typing(bool),
+        Score(i64),
+    }
+
+    assert_eq!(Chat::Leave.id(), 1);
+    assert_eq!(Chat::Typing(true).id(), 11);
+
+    for message in [
+        Chat::Join("bob".to_string()),
+        Chat::Leave,
+        Chat::Message("hi".to_string()),
+        Chat::Typing(false),
+        Chat::Score(-40),
+    ] {
+        let packet = Packet::decode(message.to_packet().encode()).unwrap();
+        assert_eq!(Chat::from_packet(packet).unwrap(), message);
+    }
+
+    assert!(matches!(
+        Chat::from_packet(Packet::Identified(5, vec![])),
+        Err(ProtocolError::UnknownId(5))
+    ));
+    assert!(matches!(
+        Chat::from_packet(Packet::Identified(12, vec![1])),
+        Err(ProtocolError::InvalidPayload(12))
+    ));
+    assert!(matches!(
+        Chat::from_packet(Packet::U8(1)),
+        Err(ProtocolError::NotIdentified(Packet::U8(1)))
+    ));
+}
+
+#[cfg(feature = "rustls")]
+#[test]
+fn check_tls_server_and_client() {
+    use rustls::{
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+        ClientConfig, RootCertStore,
+    };
+
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+        certified.signing_key.serialize_der(),
+    ));
+
+    let server = TestServer::echo(ServerBuilder::new().tls(vec![cert.clone()], key.clone_key()));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.clone()).unwrap();
+    let config = Arc::new(
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    );
+
+    let mut client = Client::connect_tls("localhost", server.port, config).unwrap();
+
+    let data = vec![9; 40_000];
+    client.send(Packet::Bytes(data.clone())).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::Bytes(data));
+
+    // A plain TCP client cannot talk to the TLS server.
+    assert!(server.client().connect().is_err());
+
+    // Unix sockets cannot carry TLS, so the server refuses to start instead of going plaintext.
+    #[cfg(unix)]
+    assert!(matches!(
+        ServerBuilder::new()
+            .unix_path(
+                std::env::temp_dir().join(format!("bitsock-tls-{}.sock", std::process::id())),
+            )
+            .tls(vec![cert], key)
+            .build()
+            .listen(),
+        Err(Error::Bind { .. })
+    ));
+}
+
+#[test]
+fn check_disconnect_is_reported() {
+    // A stream ending between frames is a clean disconnection.
+    let mut reader = Cursor::new(Packet::Invalid.frame());
+    assert_eq!(Packet::read_from(&mut reader).unwrap(), Packet::Invalid);
+    assert!(matches!(
+        Packet::read_from(&mut reader),
+        Err(Error::Disconnected)
+    ));
+
+    // A stream ending inside a frame is an error.
+    let frame = Packet::U64(1).frame();
+    let mut reader = Cursor::new(frame[..frame.len() - 1].to_vec());
+    assert!(matches!(Packet::read_from(&mut reader), Err(Error::Io(_))));
+
+    let (sender, disconnected) = mpsc::channel();
+    let server = TestServer::echo(ServerBuilder::new().disconnect_handler(Box::new(
+        move |address| sender.send(address.to_string()).unwrap(),
+    )));
+
+    let mut client = server.connect();
+    client.send(Packet::Invalid).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::Invalid);
+    client.disconnect().unwrap();
+
+    assert!(disconnected.recv_timeout(Duration::from_secs(2)).is_ok());
+
+    // A panicking handler still unregisters its client and reports the disconnection.
+    let (sender, disconnected) = mpsc::channel();
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .client_handler(Box::new(|_| panic!("handler failed")))
+            .disconnect_handler(Box::new(move |address| {
+                sender.send(address.to_string()).unwrap()
+            }))
+            .error_handler(Box::new(|_| ())),
+    );
+
+    let _client = server.connect();
+    assert!(disconnected.recv_timeout(Duration::from_secs(2)).is_ok());
+    assert!(server.handle.clients().is_empty());
+}
+
+#[test]
+fn check_heartbeat_drops_idle_clients() {
+    let (sender, errors) = mpsc::channel();
+    let server = TestServer::echo(
+        ServerBuilder::new()
+            .heartbeat(Duration::from_millis(20), Duration::from_millis(150))
+            .error_handler(Box::new(move |error| sender.send(error).unwrap())),
+    );
+
+    // A client that never reads stops answering and is dropped.
+    let _idle = server.connect();
+
+    // Pings are answered while reading, so the server keeps the client.
+    let mut client = server
+        .client()
+        .read_timeout(Duration::from_millis(400))
+        .connect()
+        .unwrap();
+    assert!(matches!(client.read(), Err(Error::Timeout)));
+    client.send(Packet::U8(7)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(7));
+
+    assert!(matches!(
+        errors.recv_timeout(Duration::from_secs(2)),
+        Ok(Error::Idle { address }) if address.starts_with("127.0.0.1:")
+    ));
+}
+
+#[test]
+fn check_client_builder_retries_and_logs() {
+    let (sender, warnings) = mpsc::channel();
+
+    // Nothing listens on this port.
+    let result = ClientBuilder::new()
+        .port(unused_port())
+        .connect_timeout(Duration::from_millis(200))
+        .retries(2)
+        .backoff(Duration::from_millis(1), Duration::from_millis(2))
+        .log_handler(Box::new(move |stage, level, _| {
+            if matches!(stage, LogStage::CLIENT) && matches!(level, LogLevel::WARN) {
+                sender.send(()).unwrap();
+            }
+        }))
+        .connect();
+    assert!(matches!(result, Err(Error::Connect { .. })));
+    assert_eq!(warnings.try_iter().count(), 2);
+
+    let server = TestServer::echo(ServerBuilder::new());
+
+    let mut client = server
+        .client()
+        .local_address("127.0.0.1", 0)
+        .nodelay(true)
+        .send_buffer_size(64 * 1024)
+        .recv_buffer_size(64 * 1024)
+        .connect()
+        .unwrap();
+    client.send(Packet::U32(12)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U32(12));
+}
+
+#[test]
+fn check_client_reconnects_and_queues() {
+    // Every connection answers a single packet, then is closed by the server.
+    let server = TestServer::start(ServerBuilder::new().client_handler(Box::new(|mut c| {
+        if let Ok(packet) = c.read() {
+            let _ = c.send(packet);
+        }
+    })));
+
+    let (sender, states) = mpsc::channel();
+    let mut client = ReconnectingClientBuilder::new(server.client())
+        .state_handler(Box::new(move |state| {
+            let _ = sender.send(state);
+        }))
+        .connect();
+
+    client.send(Packet::U8(1)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(1));
+    assert!(matches!(client.read(), Err(Error::Disconnected)));
+
+    client.send(Packet::U8(2)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(2));
+
+    let states: Vec<ConnectionState> = (0..5)
+        .map(|_| states.recv_timeout(Duration::from_secs(2)).unwrap())
+        .collect();
+    assert_eq!(
+        states,
+        [
+            ConnectionState::Connecting,
+            ConnectionState::Connected,
+            ConnectionState::Disconnected,
+            ConnectionState::Connecting,
+            ConnectionState::Connected,
+        ]
+    );
+
+    // Nothing listens on this port.
+    let port = unused_port();
+    let mut client = ReconnectingClientBuilder::new(
+        ClientBuilder::new()
+            .port(port)
+            .retries(100)
+            .backoff(Duration::from_millis(50), Duration::from_millis(50))
+            .log_handler(quiet()),
+    )
+    .queue_limit(1)
+    .connect();
+    client.send(Packet::U8(1)).unwrap();
+    assert!(matches!(
+        client.send(Packet::U8(2)),
+        Err(Error::QueueFull { limit: 1 })
+    ));
+    assert_eq!(client.queued(), 1);
+
+    let mut client =
+        ReconnectingClientBuilder::new(ClientBuilder::new().port(port).log_handler(quiet()))
+            .connect();
+    assert!(matches!(client.read(), Err(Error::Disconnected)));
+    assert_eq!(client.state(), ConnectionState::GaveUp);
+    assert!(matches!(
+        client.send(Packet::U8(1)),
+        Err(Error::Disconnected)
+    ));
+}
+
+#[test]
+fn check_server_registry_reaches_clients() {
+    // Handlers start once their client is registered, and disconnections are reported after
+    // the client is unregistered.
+    let (entered, registered) = mpsc::channel();
+    let (sender, disconnected) = mpsc::channel();
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .client_handler(Box::new(move |mut c| {
+                entered.send(()).unwrap();
+                while let Ok(packet) = c.read() {
+                    let _ = c.server().broadcast(packet);
+                }
+            }))
+            .disconnect_handler(Box::new(move |_| sender.send(()).unwrap())),
+    );
+    let handle = &server.handle;
+
+    let mut first = server.connect();
+    let mut second = server.connect();
+    for _ in 0..2 {
+        registered.recv_timeout(Duration::from_secs(2)).unwrap();
+    }
+    let clients = handle.clients();
+    assert_eq!(clients.len(), 2);
+
+    first.send(Packet::String("hi".to_string())).unwrap();
+    assert_eq!(first.read().unwrap(), Packet::String("hi".to_string()));
+    assert_eq!(second.read().unwrap(), Packet::String("hi".to_string()));
+
+    handle.send_to(clients[1].id, Packet::U8(2)).unwrap();
+    assert_eq!(second.read().unwrap(), Packet::U8(2));
+
+    handle.disconnect(clients[0].id).unwrap();
+    assert!(matches!(first.read(), Err(Error::Disconnected)));
+    disconnected.recv_timeout(Duration::from_secs(2)).unwrap();
+    let remaining: Vec<u64> = handle.clients().iter().map(|c| c.id).collect();
+    assert_eq!(remaining, [clients[1].id]);
+    assert!(matches!(
+        handle.send_to(clients[0].id, Packet::U8(1)),
+        Err(Error::Disconnected)
+    ));
+}
+
+#[test]
+fn check_rooms_publish_to_members() {
+    // The first packet of a client names its room, the next ones are published to it.
+    let (sender, disconnected) = mpsc::channel();
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .client_handler(Box::new(|mut c| {
+                let room = match c.read() {
+                    Ok(Packet::String(room)) => room,
+                    _ => return,
+                };
+                c.join(&room).unwrap();
+                let _ = c.send(Packet::Invalid);
+
+                while let Ok(packet) = c.read() {
+                    let _ = c.publish(&room, packet);
+                }
+            }))
+            .disconnect_handler(Box::new(move |_| sender.send(()).unwrap())),
+    );
+    let handle = &server.handle;
+
+    let join = |room: &str| {
+        let mut client = server.connect();
+        client.send(Packet::String(room.to_string())).unwrap();
+        assert_eq!(client.read().unwrap(), Packet::Invalid);
+        client
+    };
+    let mut first = join("red");
+    let mut second = join("red");
+    let mut third = join("blue");
+    assert_eq!(handle.rooms(), ["blue", "red"]);
+
+    first.send(Packet::U8(1)).unwrap();
+    assert_eq!(first.read().unwrap(), Packet::U8(1));
+    assert_eq!(second.read().unwrap(), Packet::U8(1));
+
+    assert_eq!(handle.publish("blue", Packet::U8(2)).unwrap(), 1);
+    assert_eq!(third.read().unwrap(), Packet::U8(2));
+
+    // Members are removed from their rooms once disconnected.
+    let red = handle.members("red");
+    assert_eq!(red.len(), 2);
+    second.disconnect().unwrap();
+    disconnected.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(handle.members("red"), [red[0]]);
+
+    handle.leave(red[0], "red");
+    assert_eq!(handle.rooms(), ["blue"]);
+    assert_eq!(handle.publish("red", Packet::U8(3)).unwrap(), 0);
+}
+
+#[test]
+fn check_split_halves_are_full_duplex() {
+    fn assert_send<T: Send>() {}
+    assert_send::<PacketReader>();
+    assert_send::<PacketWriter>();
+
+    // The server pushes packets from another thread while echoing the client.
+    let server = TestServer::start(ServerBuilder::new().client_handler(Box::new(|c| {
+        let (mut reader, writer) = c.split().unwrap();
+        let pusher = writer.clone();
+        let pushing = thread::spawn(move || {
+            for i in 0..3 {
+                pusher.send(Packet::U32(i)).unwrap();
+            }
+        });
+
+        while let Ok(packet) = reader.read() {
+            if writer.send(packet).is_err() {
+                break;
+            }
+        }
+        pushing.join().unwrap();
+    })));
+
+    let (mut reader, writer) = server.connect().split().unwrap();
+    let sending = thread::spawn(move || {
+        writer.send(Packet::String("echo".to_string())).unwrap();
+        writer
+    });
+
+    let mut received: Vec<Packet> = (0..4).map(|_| reader.read().unwrap()).collect();
+    received.retain(|packet| *packet != Packet::String("echo".to_string()));
+    assert_eq!(received, [Packet::U32(0), Packet::U32(1), Packet::U32(2)]);
+
+    sending.join().unwrap().disconnect().unwrap();
+    assert!(matches!(reader.read(), Err(Error::Disconnected)));
+}
+
+#[test]
+fn check_rpc_calls_are_answered() {
+    let server = TestServer::echo(
+        ServerBuilder::new()
+            .rpc_handler(
+                1,
+                Box::new(|payload| match payload {
+                    Packet::Bytes(numbers) => {
+                        Ok(Packet::U32(numbers.iter().map(|n| *n as u32).sum()))
+                    }
+                    _ => Err("expected bytes".to_string()),
+                }),
+            )
+            .rpc_handler(
+                2,
+                Box::new(|_| {
+                    thread::sleep(Duration::from_millis(300));
+                    Ok(Packet::Invalid)
+                }),
+            ),
+    );
+
+    let client = Arc::new(RpcClient::new(server.connect()).unwrap());
+    let timeout = Duration::from_secs(2);
+
+    assert_eq!(
+        client.call(1, Packet::Bytes(vec![2, 3]), timeout).unwrap(),
+        Packet::U32(5)
+    );
+    assert!(matches!(
+        client.call(1, Packet::Invalid, timeout),
+        Err(RpcError::Remote(e)) if e == "expected bytes"
+    ));
+    assert!(matches!(
+        client.call(9, Packet::Invalid, timeout),
+        Err(RpcError::UnknownMethod(9))
+    ));
+    assert!(matches!(
+        client.call(9, Packet::Invalid, timeout).map_err(Error::from),
+        Err(Error::Rpc(e)) if matches!(*e, RpcError::UnknownMethod(9))
+    ));
+
+    // Packets that are not replies still reach the client.
+    client.send(Packet::U8(9)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(9));
+
+    let calls: Vec<_> = (0..4u8)
+        .map(|i| {
+            let client = client.clone();
+            thread::spawn(move || client.call(1, Packet::Bytes(vec![i, i]), timeout))
+        })
+        .collect();
+    for (i, call) in calls.into_iter().enumerate() {
+        assert_eq!(call.join().unwrap().unwrap(), Packet::U32(i as u32 * 2));
+    }
+
+    assert!(matches!(
+        client.call(2, Packet::Invalid, Duration::from_millis(50)),
+        Err(RpcError::Timeout)
+    ));
+    assert!(matches!(Error::from(RpcError::Timeout), Error::Timeout));
+
+    client.disconnect().unwrap();
+    assert!(matches!(
+        client.call(1, Packet::Bytes(vec![1]), timeout),
+        Err(RpcError::Connection(_))
+    ));
+
+    // Without a client handler, the default one keeps the client connected for its calls.
+    let server = TestServer::start(ServerBuilder::new().rpc_handler(1, Box::new(Ok)));
+
+    let client = RpcClient::new(server.connect()).unwrap();
+    assert_eq!(
+        client.call(1, Packet::U8(3), timeout).unwrap(),
+        Packet::U8(3)
+    );
+
+    // Calls are answered while the handler does not read, malformed ones get an error.
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .rpc_handler(1, Box::new(Ok))
+            .client_handler(Box::new(move |c| {
+                let _ = released.lock().unwrap().recv();
+                drop(c);
+            })),
+    );
+
+    let mut client = server.connect();
+    let mut call = 1u32.to_le_bytes().to_vec();
+    call.extend_from_slice(&77u64.to_le_bytes());
+    client
+        .send(Packet::Identified(crate::RPC_REQUEST_ID, call.clone()))
+        .unwrap();
+    assert!(matches!(
+        client.read().unwrap(),
+        Packet::Identified(crate::RPC_ERROR_ID, body) if body[..8] == 77u64.to_le_bytes()
+    ));
+
+    call.extend_from_slice(&Packet::U8(4).encode());
+    client
+        .send(Packet::Identified(crate::RPC_REQUEST_ID, call))
+        .unwrap();
+    assert!(matches!(
+        client.read().unwrap(),
+        Packet::Identified(crate::RPC_RESPONSE_ID, body) if body[8..] == Packet::U8(4).encode()
+    ));
+
+    release.send(()).unwrap();
+    assert!(matches!(client.read(), Err(Error::Disconnected)));
+}
+
+#[test]
+fn check_udp_peers_are_tracked_and_expired() {
+    let (sender, expired) = mpsc::channel();
+    let mut server = UdpServerBuilder::new()
+        .address("127.0.0.1")
+        .port(0)
+        .peer_timeout(Duration::from_millis(200))
+        .packet_handler(Box::new(|peer, packet| {
+            peer.send(packet).unwrap();
+        }))
+        .disconnect_handler(Box::new(move |address| {
+            sender.send(address.to_string()).unwrap()
+        }))
+        .log_handler(quiet())
+        .build();
+    server.listen().unwrap();
+    let address = server.local_addr().unwrap();
+    let handle = server.handle();
+    let running = thread::spawn(move || server.run());
+
+    let client = UdpClient::connect("127.0.0.1", address.port()).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    client.send(Packet::String("state".to_string())).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::String("state".to_string()));
+    assert_eq!(handle.peers().len(), 1);
+
+    let peer = handle.peers()[0];
+    assert_eq!(handle.broadcast(Packet::U16(7)).unwrap(), 1);
+    assert_eq!(client.read().unwrap(), Packet::U16(7));
+
+    // Datagrams that cannot be decoded are only counted, their sender is not a peer.
+    let garbage = UdpSocket::bind("127.0.0.1:0").unwrap();
+    garbage.send_to(&[0xFF, 1, 2], address).unwrap();
+    client.send(Packet::U8(1)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(1));
+    assert_eq!(handle.invalid_datagrams(), 1);
+    assert_eq!(handle.peers(), vec![peer]);
+
+    assert!(matches!(
+        client.send(Packet::Bytes(vec![0; crate::udp::MAX_DATAGRAM_SIZE])),
+        Err(Error::FrameTooLarge { .. })
+    ));
+
+    let expired = expired.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(expired, peer.to_string());
+    assert!(handle.peers().is_empty());
+
+    handle.shutdown();
+    running.join().unwrap();
+    assert!(matches!(
+        handle.send_to(peer, Packet::Invalid),
+        Err(Error::Disconnected)
+    ));
+}
+
+/// Socket shim dropping about a third of the datagrams it sends.
+struct LossySocket {
+    socket: UdpSocket,
+    state: std::cell::Cell<u32>,
+}
+
+impl LossySocket {
+    /// Wraps `socket`, `seed` picks which datagrams are dropped.
+    fn new(socket: UdpSocket, seed: u32) -> Self {
+        Self {
+            socket,
+            state: std::cell::Cell::new(seed),
+        }
+    }
+}
+
+impl DatagramSocket for LossySocket {
+    fn send(&self, datagram: &[u8]) -> std::io::Result<usize> {
+        // Xorshift, so resent datagrams are not always dropped at the same position.
+        let mut state = self.state.get();
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.state.set(state);
+        if state.is_multiple_of(3) {
+            return Ok(datagram.len());
+        }
+        self.socket.send(datagram)
+    }
+
+    fn recv(&self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.socket.recv(buffer)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+}
+
+#[test]
+fn check_reliable_channels_survive_loss() {
+    let (first, second) = udp_pair();
+    let mut sender = ReliableConnection::new(LossySocket::new(first, 48118));
+    let mut receiver = ReliableConnection::new(LossySocket::new(second, 48119));
+    receiver.set_read_timeout(Some(Duration::from_millis(50)));
+    let (flushed, done) = mpsc::channel::<()>();
+
+    let running = thread::spawn(move || {
+        let mut ordered = Vec::new();
+        let mut unordered = Vec::new();
+        let mut unreliable = 0;
+        // Keep reading after the last packet so lost acknowledgements are sent again.
+        while done.try_recv().is_err() {
+            let (channel, packet) = match receiver.read() {
+                Ok(received) => received,
+                Err(Error::Timeout) => continue,
+                Err(e) => panic!("{}", e),
+            };
+            match (channel, packet) {
+                (Channel::ReliableOrdered, Packet::U32(n)) => ordered.push(n),
+                (Channel::ReliableUnordered, Packet::U32(n)) => unordered.push(n),
+                (Channel::Unreliable, _) => unreliable += 1,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        (ordered, unordered, unreliable)
+    });
+
+    for n in 0..30 {
+        sender
+            .send(Channel::ReliableOrdered, Packet::U32(n))
+            .unwrap();
+        sender
+            .send(Channel::ReliableUnordered, Packet::U32(n))
+            .unwrap();
+        sender.send(Channel::Unreliable, Packet::Invalid).unwrap();
+    }
+    assert!(sender.pending() > 0);
+    sender.flush(Duration::from_secs(5)).unwrap();
+    assert_eq!(sender.pending(), 0);
+    flushed.send(()).unwrap();
+
+    let (ordered, mut unordered, unreliable) = running.join().unwrap();
+    assert_eq!(ordered, (0..30).collect::<Vec<_>>());
+    unordered.sort();
+    assert_eq!(unordered, (0..30).collect::<Vec<_>>());
+    assert!(unreliable < 30);
+}
+
+#[test]
+fn check_reliable_connection_skips_bad_datagrams() {
+    let (socket, peer) = udp_pair();
+    peer.set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut connection = ReliableConnection::new(socket);
+    connection.set_read_timeout(Some(Duration::from_millis(200)));
+
+    // Kind, channel, then the sequence, as written by the reliable connection.
+    let ordered = |sequence: u32, packet: Packet| {
+        let mut datagram = vec![0, 2];
+        datagram.extend_from_slice(&sequence.to_le_bytes());
+        datagram.extend(packet.encode());
+        peer.send(&datagram).unwrap();
+    };
+    let mut ack = [0; 16];
+
+    // Undecodable datagrams are counted and skipped.
+    peer.send(&[9, 9]).unwrap();
+    // Sequences past the window are dropped without acknowledgement.
+    ordered(crate::reliable::RECEIVE_WINDOW, Packet::U8(0));
+    ordered(1, Packet::U8(2));
+    ordered(0, Packet::U8(1));
+
+    assert_eq!(
+        connection.read().unwrap(),
+        (Channel::ReliableOrdered, Packet::U8(1))
+    );
+    assert_eq!(
+        connection.read().unwrap(),
+        (Channel::ReliableOrdered, Packet::U8(2))
+    );
+    assert!(matches!(connection.read(), Err(Error::Timeout)));
+    assert_eq!(connection.invalid_datagrams(), 1);
+
+    let mut acked = Vec::new();
+    while let Ok(size) = peer.recv(&mut ack) {
+        acked.push(u32::from_le_bytes(ack[2..size].try_into().unwrap()));
+    }
+    acked.sort();
+    assert_eq!(acked, vec![0, 1]);
+}
+
+#[cfg(unix)]
+#[test]
+fn check_unix_socket_transport() {
+    use std::os::unix::{fs::PermissionsExt, net::UnixListener};
+
+    let path = std::env::temp_dir().join(format!("bitsock-{}.sock", std::process::id()));
+    // Socket file left behind by a server that did not stop cleanly.
+    drop(UnixListener::bind(&path).unwrap());
+
+    let server = TestServer::echo(
+        ServerBuilder::new()
+            .unix_path(&path)
+            .unix_permissions(0o600),
+    );
+
+    let mut client = Client::connect_unix(&path).unwrap();
+    client.send(Packet::String("local".to_string())).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::String("local".to_string()));
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    // Unix clients go through the same handshake as TCP ones.
+    assert!(matches!(
+        ClientBuilder::new()
+            .protocol("other", 1)
+            .connect_unix(&path),
+        Err(Error::Handshake(_))
+    ));
+
+    // A second server cannot take over the socket of a running one.
+    let (sender, errors) = mpsc::channel();
+    ServerBuilder::new()
+        .unix_path(&path)
+        .error_handler(Box::new(move |e| sender.send(e).unwrap()))
+        .log_handler(quiet())
+        .build()
+        .run();
+    assert!(matches!(errors.recv().unwrap(), Error::Bind { .. }));
+
+    assert!(server.shutdown(Duration::from_secs(2)));
+    assert!(matches!(client.read(), Err(Error::Disconnected)));
+    assert!(!path.exists());
+}
+
+#[cfg(feature = "websocket")]
+#[test]
+fn check_websocket_and_native_clients_share_a_server() {
+    let server = TestServer::echo(ServerBuilder::new().websocket(true));
+    let url = format!("ws://127.0.0.1:{}/", server.port);
+
+    let mut native = server.connect();
+    native.send(Packet::I64(-4)).unwrap();
+    assert_eq!(native.read().unwrap(), Packet::I64(-4));
+
+    let mut client = Client::connect_websocket(&url).unwrap();
+    client
+        .send(Packet::String("dashboard".to_string()))
+        .unwrap();
+    client.send(Packet::Bytes(vec![1, 2, 3])).unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        Packet::String("dashboard".to_string())
+    );
+    assert_eq!(client.read().unwrap(), Packet::Bytes(vec![1, 2, 3]));
+
+    // Browsers send one encoded packet per binary message, starting with their hello.
+    let tcp = std::net::TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    let (mut browser, _) = tungstenite::client(&url, tcp).unwrap();
+    browser
+        .send(tungstenite::Message::binary(
+            Hello::default().to_packet().encode(),
+        ))
+        .unwrap();
+    let hello = browser.read().unwrap().into_data();
+    assert_eq!(
+        Hello::from_packet(&Packet::decode(hello.to_vec()).unwrap()),
+        Some(Hello::default())
+    );
+    browser
+        .send(tungstenite::Message::binary(Packet::U32(7).encode()))
+        .unwrap();
+    assert_eq!(
+        browser.read().unwrap(),
+        tungstenite::Message::binary(Packet::U32(7).encode())
+    );
+    // Counted before closing, the server drops the browser as soon as it sees the close.
+    assert_eq!(server.handle.clients().len(), 3);
+    browser.close(None).unwrap();
+
+    client.disconnect().unwrap();
+    assert!(server.shutdown(Duration::from_secs(2)));
+}
+
+#[test]
+fn check_clients_report_peer_address_and_traffic() {
+    // The echo can arrive before the server counted it as sent, so the handler says when it did.
+    let (sender, echoed) = mpsc::channel();
+    let server = TestServer::start(ServerBuilder::new().client_handler(Box::new(move |mut c| {
+        while let Ok(packet) = c.read() {
+            if c.send(packet).is_err() {
+                break;
+            }
+            sender.send(()).unwrap();
+        }
+    })));
+    let before = std::time::SystemTime::now();
+
+    let mut client = server.connect();
+    for packet in [Packet::U8(1), Packet::String("stats".to_string())] {
+        client.send(packet.clone()).unwrap();
+        assert_eq!(client.read().unwrap(), packet);
+        echoed.recv_timeout(Duration::from_secs(2)).unwrap();
+    }
+
+    // Both peers announce the default hello first.
+    let bytes = (Hello::default().to_packet().frame().len()
+        + Packet::U8(1).frame().len()
+        + Packet::String("stats".to_string()).frame().len()) as u64;
+    let info = server.handle.clients().remove(0);
+    assert_eq!(
+        info.stats,
+        crate::server::ConnectionStats {
+            bytes_sent: bytes,
+            bytes_received: bytes,
+            packets_sent: 3,
+            packets_received: 3,
+        }
+    );
+
+    // The address is the one of the client, not the one the server listens on.
+    let peer_addr = info.peer_addr.unwrap();
+    assert!(peer_addr.ip().is_loopback());
+    assert_ne!(peer_addr.port(), server.port);
+    assert_eq!(info.address, peer_addr.to_string());
+    assert!(info.connected_at >= before);
+}
+
+#[test]
+fn check_extensions_are_shared_with_the_registry() {
+    struct Player(String);
+    struct Score(u32);
+
+    // The first packet names the player, the next ones ask for its score.
+    let server = TestServer::start(ServerBuilder::new().client_handler(Box::new(|mut c| {
+        if let Ok(Packet::String(name)) = c.read() {
+            c.extensions().insert(Player(name));
+            let _ = c.send(Packet::Invalid);
+        }
+        while c.read().is_ok() {
+            let score = c.extensions().get::<Score>().map_or(0, |s| s.0);
+            let _ = c.send(Packet::U32(score));
+        }
+    })));
+    let handle = &server.handle;
+
+    let mut client = server.connect();
+    client.send(Packet::String("ana".to_string())).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::Invalid);
+
+    let id = handle.clients()[0].id;
+    let name = handle.with_extensions(id, |e| e.get::<Player>().map(|p| p.0.clone()));
+    assert_eq!(name.unwrap(), Some("ana".to_string()));
+
+    handle.with_extensions(id, |e| e.insert(Score(12))).unwrap();
+    client.send(Packet::Invalid).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U32(12));
+
+    assert!(matches!(
+        handle.with_extensions(id + 1, |e| e.len()),
+        Err(Error::Disconnected)
+    ));
+}
+
+#[test]
+fn check_auth_rejects_clients_before_the_handler() {
+    let server = TestServer::echo(
+        ServerBuilder::new()
+            .auth_handler(auth::token("open sesame"))
+            .auth_timeout(Duration::from_millis(100)),
+    );
+
+    let rejected = server
+        .client()
+        .auth_handler(auth::send_token("guess"))
+        .connect();
+    assert!(matches!(rejected, Err(Error::AuthRejected(reason)) if reason == "invalid token"));
+
+    let mut client = server
+        .client()
+        .auth_handler(auth::send_token("open sesame"))
+        .connect()
+        .unwrap();
+    client.send(Packet::U8(7)).unwrap();
+    assert_eq!(client.read().unwrap(), Packet::U8(7));
+    assert_eq!(server.handle.clients().len(), 1);
+
+    // The hello says the server requires authentication, so a client without a handler stops.
+    assert!(matches!(
+        server.client().connect(),
+        Err(Error::Handshake(reason)) if reason.contains("requires authentication")
+    ));
+
+    // A client that never sends its token is rejected once the auth timeout elapsed.
+    let silent = server.client().auth_handler(Box::new(|_| Ok(()))).connect();
+    assert!(matches!(silent, Err(Error::AuthRejected(reason)) if reason == "operation timed out"));
+    assert_eq!(server.handle.clients().len(), 1);
+}
+
+#[cfg(feature = "hmac")]
+#[test]
+fn check_hmac_challenge_response() {
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .auth_handler(auth::hmac_challenge("shared secret"))
+            .client_handler(Box::new(|mut c| {
+                let _ = c.send(Packet::String("welcome".to_string()));
+            })),
+    );
+
+    let builder = |secret: &str| server.client().auth_handler(auth::hmac_response(secret));
+
+    let mut client = builder("shared secret").connect().unwrap();
+    assert_eq!(
+        client.read().unwrap(),
+        Packet::String("welcome".to_string())
+    );
+
+    assert!(matches!(
+        builder("wrong secret").connect(),
+        Err(Error::AuthRejected(reason)) if reason == "invalid challenge response"
+    ));
+}
+
+#[test]
+fn check_hello_negotiates_protocol_and_capabilities() {
+    let server = TestServer::start(
+        ServerBuilder::new()
+            .protocol("chat", 2)
+            .hello_timeout(Duration::from_millis(100))
+            .capabilities(Capabilities {
+                encryption: false,
+                max_frame_size: 64,
+            })
+            .client_handler(Box::new(|mut c| {
+                let max_frame_size = c.negotiated().capabilities.max_frame_size;
+                let _ = c.send(Packet::U64(max_frame_size as u64));
+            })),
+    );
+
+    let builder = |version: u32| {
+        server
+            .client()
+            .protocol("chat", version)
+            .capabilities(Capabilities {
+                encryption: true,
+                max_frame_size: 1024,
+            })
+    };
+
+    let mut client = builder(2).connect().unwrap();
+    let agreed = client.negotiated();
+    assert_eq!(agreed.protocol, "chat");
+    assert_eq!(
+        agreed.capabilities,
+        Capabilities {
+            encryption: false,
+            max_frame_size: 64,
+        }
+    );
+    assert_eq!(client.read().unwrap(), Packet::U64(64));
+    assert!(matches!(
+        client.send(Packet::Bytes(vec![0; 64])),
+        Err(Error::FrameTooLarge { max: 64, .. })
+    ));
+
+    assert!(matches!(
+        builder(3).connect(),
+        Err(Error::Handshake(reason)) if reason.contains("protocol version 3")
+    ));
+
+    // A client that never sends its hello is dropped after the hello timeout.
+    let mut silent = std::net::TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    silent
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let closed = std::io::Read::read(&mut silent, &mut [0]);
+    assert!(closed.map_or_else(
+        |e| e.kind() == std::io::ErrorKind::ConnectionReset,
+        |n| n == 0
+    ));
+
+    // Frames bigger than the agreed size are refused when read too.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        Packet::read_from(&mut stream).unwrap();
+        let hello = Hello {
+            capabilities: Capabilities {
+                max_frame_size: 64,
+                ..Capabilities::default()
+            },
+            ..Hello::default()
+        };
+        hello.to_packet().write_to(&mut stream).unwrap();
+        Packet::Bytes(vec![0; 100]).write_to(&mut stream).unwrap();
+        stream
+    });
+    let mut client = Client::connect("127.0.0.1", port).unwrap();
+    assert!(matches!(
+        client.read(),
+        Err(Error::FrameTooLarge { max: 64, .. })
+    ));
+    peer.join().unwrap();
+}
+
+#[test]
+fn check_a_frame_cut_by_the_read_timeout_closes_the_connection() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (release, released) = mpsc::channel::<()>();
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        Packet::read_from(&mut stream).unwrap();
+        Hello::default().to_packet().write_to(&mut stream).unwrap();
+
+        // Only the start of the frame arrives before the timeout.
+        let frame = Packet::String("cut".to_string()).frame();
+        std::io::Write::write_all(&mut stream, &frame[..3]).unwrap();
+        let _ = released.recv();
+        let _ = std::io::Write::write_all(&mut stream, &frame[3..]);
+    });
+
+    let mut client = ClientBuilder::new()
+        .port(port)
+        .read_timeout(Duration::from_millis(200))
+        .connect()
+        .unwrap();
+    assert!(matches!(client.read(), Err(Error::ConnectionLost(_))));
+
+    // The end of the frame is never taken for the start of another one.
+    release.send(()).unwrap();
+    assert!(matches!(
+        client.read(),
+        Err(Error::Disconnected | Error::ConnectionLost(_))
+    ));
+    peer.join().unwrap();
+}
+
+#[test]
+fn check_reliable_connection_gives_up_on_a_silent_peer() {
+    // The peer never reads, so nothing is acknowledged.
+    let (socket, _peer) = udp_pair();
+    let mut connection = ReliableConnection::new(socket);
+    connection.set_max_resends(2);
+
+    for n in 0..crate::reliable::SEND_WINDOW {
+        connection
+            .send(Channel::ReliableOrdered, Packet::U32(n))
+            .unwrap();
+    }
+    assert_eq!(connection.pending(), crate::reliable::SEND_WINDOW as usize);
+
+    // The window is full, so the next packet waits until the connection gives up.
+    assert!(matches!(
+        connection.send(Channel::ReliableOrdered, Packet::Invalid),
+        Err(Error::Disconnected)
+    ));
+    assert_eq!(connection.pending(), crate::reliable::SEND_WINDOW as usize);
+    assert!(matches!(
+        connection.flush(Duration::from_secs(1)),
+        Err(Error::Disconnected)
+    ));
+}
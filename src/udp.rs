@@ -0,0 +1,441 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    server::{DisconnectHandler, ErrorHandler, LogHandler},
+    Error, LogLevel, LogStage, Packet,
+};
+
+/// Maximum size in bytes of an encoded packet sent as a single datagram.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Interval at which the server checks whether it is shutting down and expires idle peers.
+const RECV_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Handler called with every packet received by the UDP server and the peer that sent it.
+pub type PacketHandler = Box<dyn Fn(UdpPeer, Packet) + Send + Sync>;
+
+/// Encodes a packet, failing if it does not fit in a datagram.
+fn encode(packet: &Packet) -> Result<Vec<u8>, Error> {
+    let bytes = packet.encode();
+    if bytes.len() > MAX_DATAGRAM_SIZE {
+        return Err(Error::FrameTooLarge {
+            size: bytes.len(),
+            max: MAX_DATAGRAM_SIZE,
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// Receives a datagram in `buffer` and decodes the packet it carries.
+fn receive(socket: &UdpSocket, buffer: &mut [u8]) -> Result<(Packet, SocketAddr), Error> {
+    let (size, address) = socket.recv_from(buffer)?;
+
+    Ok((Packet::decode_slice(&buffer[..size])?, address))
+}
+
+/// Returns a buffer large enough to receive any datagram.
+pub(crate) fn receive_buffer() -> Vec<u8> {
+    vec![0; MAX_DATAGRAM_SIZE + 1]
+}
+
+/// Binds a socket on an ephemeral port and connects it to the given address and port.
+pub(crate) fn connected_socket(address: &str, port: u16) -> Result<UdpSocket, Error> {
+    let address = format!("{}:{}", address, port);
+    let error = |source| Error::Connect {
+        address: address.clone(),
+        source,
+    };
+
+    let remote = address
+        .to_socket_addrs()
+        .map_err(error)?
+        .next()
+        .ok_or_else(|| error(std::io::Error::from(ErrorKind::InvalidInput)))?;
+    let local: SocketAddr = if remote.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+
+    let socket = UdpSocket::bind(local).map_err(error)?;
+    socket.connect(remote).map_err(error)?;
+
+    Ok(socket)
+}
+
+/// UDP client data structure, every packet is sent as a single datagram.
+/// Datagrams can be lost, duplicated or arrive out of order.
+pub struct UdpClient {
+    socket: UdpSocket,
+    buffer: Mutex<Vec<u8>>,
+}
+
+impl UdpClient {
+    /// Bind a local socket and connect it to the server with given address and port.
+    pub fn connect(address: &str, port: u16) -> Result<Self, Error> {
+        Ok(Self {
+            socket: connected_socket(address, port)?,
+            buffer: Mutex::new(receive_buffer()),
+        })
+    }
+
+    /// Send a [Packet] to the server.
+    pub fn send(&self, packet: Packet) -> Result<usize, Error> {
+        Ok(self.socket.send(&encode(&packet)?)?)
+    }
+
+    /// Listen to a [Packet] from the server.
+    pub fn read(&self) -> Result<Packet, Error> {
+        Ok(receive(&self.socket, &mut self.buffer.lock().unwrap())?.0)
+    }
+
+    /// Sets how long [UdpClient::read] waits for a packet before returning [Error::Timeout].
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+}
+
+/// Peer that sent a packet to a [UdpServer].
+#[derive(Clone)]
+pub struct UdpPeer {
+    address: SocketAddr,
+    socket: Arc<UdpSocket>,
+}
+
+impl UdpPeer {
+    /// Send a [Packet] to the peer.
+    pub fn send(&self, packet: Packet) -> Result<usize, Error> {
+        Ok(self.socket.send_to(&encode(&packet)?, self.address)?)
+    }
+
+    /// Get the address of the peer.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+/// State shared between a [UdpServer] and its [UdpServerHandle]s.
+struct Shared {
+    stopping: AtomicBool,
+    socket: Mutex<Option<Arc<UdpSocket>>>,
+    peers: Mutex<HashMap<SocketAddr, Instant>>,
+    invalid_datagrams: AtomicU64,
+}
+
+/// Handle used to reach the peers of a running [UdpServer] and to stop it from any thread.
+#[derive(Clone)]
+pub struct UdpServerHandle {
+    shared: Arc<Shared>,
+}
+
+impl UdpServerHandle {
+    /// Stop the server, [UdpServer::run] returns within a few milliseconds.
+    pub fn shutdown(&self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns the addresses of the peers that sent a packet recently, sorted.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        let mut peers: Vec<SocketAddr> =
+            self.shared.peers.lock().unwrap().keys().copied().collect();
+        peers.sort();
+
+        peers
+    }
+
+    /// Returns the number of datagrams that were dropped because they could not be decoded.
+    pub fn invalid_datagrams(&self) -> u64 {
+        self.shared.invalid_datagrams.load(Ordering::Relaxed)
+    }
+
+    /// Send a [Packet] to a peer.
+    /// Returns [Error::Disconnected] if the server is not running.
+    pub fn send_to(&self, address: SocketAddr, packet: Packet) -> Result<usize, Error> {
+        match &*self.shared.socket.lock().unwrap() {
+            Some(socket) => Ok(socket.send_to(&encode(&packet)?, address)?),
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// Send a [Packet] to every known peer, returns the number of peers it was sent to.
+    pub fn broadcast(&self, packet: Packet) -> Result<usize, Error> {
+        let bytes = encode(&packet)?;
+        let socket = match &*self.shared.socket.lock().unwrap() {
+            Some(socket) => socket.clone(),
+            None => return Err(Error::Disconnected),
+        };
+
+        Ok(self
+            .peers()
+            .into_iter()
+            .filter(|address| socket.send_to(&bytes, address).is_ok())
+            .count())
+    }
+}
+
+/// UDP server data structure, packets are handled one at a time on the thread running the server.
+pub struct UdpServer<'a> {
+    pub address: &'a str,
+    pub port: u16,
+    peer_timeout: Duration,
+    shared: Arc<Shared>,
+    error_handler: Option<ErrorHandler>,
+    packet_handler: PacketHandler,
+    log_handler: Option<LogHandler>,
+    disconnect_handler: Option<DisconnectHandler>,
+}
+
+impl<'a> UdpServer<'a> {
+    /// Returns a [UdpServerHandle] that can be used from other threads to reach the peers and
+    /// stop the server.
+    pub fn handle(&self) -> UdpServerHandle {
+        UdpServerHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Bind the server without receiving packets yet, so [UdpServer::local_addr] is known before
+    /// [UdpServer::run] is called. [UdpServer::run] binds the server itself otherwise.
+    pub fn listen(&mut self) -> Result<(), Error> {
+        self.bind().map(|_| ())
+    }
+
+    /// Returns the address the server is bound to, to find the port picked for port `0`.
+    /// Returns [None] until the server is bound.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        let socket = self.shared.socket.lock().unwrap();
+        socket.as_ref().and_then(|socket| socket.local_addr().ok())
+    }
+
+    /// Start the server execution, this will receive packets until [UdpServerHandle::shutdown]
+    /// is called or the socket fails to bind.
+    pub fn run(&mut self) {
+        self.log(LogLevel::INFO, "Starting server");
+
+        let socket = match self.bind() {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.handle_error(e);
+                return;
+            }
+        };
+
+        self.log(LogLevel::INFO, "Server started, listening for packets.");
+
+        let mut buffer = receive_buffer();
+        while !self.shared.stopping.load(Ordering::SeqCst) {
+            match receive(&socket, &mut buffer) {
+                Ok((packet, address)) => {
+                    let known = self
+                        .shared
+                        .peers
+                        .lock()
+                        .unwrap()
+                        .insert(address, Instant::now())
+                        .is_some();
+                    if !known {
+                        self.log(LogLevel::INFO, &format!("Peer {} joined.", address));
+                    }
+
+                    let peer = UdpPeer {
+                        address,
+                        socket: socket.clone(),
+                    };
+                    (self.packet_handler)(peer, packet);
+                }
+                Err(Error::Timeout) => {}
+                // Anyone can send datagrams, reporting each one would let them flood the handler.
+                Err(Error::Decode { .. }) => {
+                    self.shared
+                        .invalid_datagrams
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => self.handle_error(e),
+            }
+
+            self.expire_peers();
+        }
+
+        *self.shared.socket.lock().unwrap() = None;
+        self.shared.peers.lock().unwrap().clear();
+        self.log(LogLevel::INFO, "Server stopped.");
+    }
+
+    /// Internal function, binds the socket unless [UdpServer::listen] already did.
+    fn bind(&self) -> Result<Arc<UdpSocket>, Error> {
+        let mut bound = self.shared.socket.lock().unwrap();
+        if let Some(socket) = &*bound {
+            return Ok(socket.clone());
+        }
+
+        let address = format!("{}:{}", self.address, self.port);
+        let socket = UdpSocket::bind(&address).map_err(|source| Error::Bind { address, source })?;
+        socket.set_read_timeout(Some(RECV_POLL_INTERVAL))?;
+
+        Ok(bound.insert(Arc::new(socket)).clone())
+    }
+
+    /// Internal function, forgets the peers that did not send anything for the peer timeout.
+    fn expire_peers(&self) {
+        let mut expired = Vec::new();
+        self.shared
+            .peers
+            .lock()
+            .unwrap()
+            .retain(|address, last_seen| {
+                let alive = last_seen.elapsed() < self.peer_timeout;
+                if !alive {
+                    expired.push(*address);
+                }
+                alive
+            });
+
+        for address in expired {
+            self.log(LogLevel::INFO, &format!("Peer {} expired.", address));
+            if let Some(handler) = &self.disconnect_handler {
+                handler(&address.to_string());
+            }
+        }
+    }
+
+    /// Internal function, used to handle errors propagated by the server.
+    fn handle_error(&self, error: Error) {
+        if let Some(handler) = &self.error_handler {
+            handler(error);
+        } else {
+            println!("{}", crate::error::report(&error));
+        }
+    }
+
+    /// Log a message from the UDP server.
+    pub fn log(&self, level: LogLevel, message: &str) {
+        if let Some(handler) = &self.log_handler {
+            handler(LogStage::SERVER, level, message);
+        } else {
+            println!("[SERVER][{:?}]: {}", level, message);
+        }
+    }
+}
+
+/// UDP server builder object, works like [ServerBuilder](crate::server::ServerBuilder).
+/// ```
+/// use bitsock::udp::UdpServerBuilder;
+///
+/// let server = UdpServerBuilder::new()
+///     .port(4445)
+///     .packet_handler(Box::new(|peer, packet| {
+///         let _ = peer.send(packet);
+///     }))
+///     .build();
+/// ```
+pub struct UdpServerBuilder<'a> {
+    address: &'a str,
+    port: u16,
+    peer_timeout: Duration,
+    error_handler: Option<ErrorHandler>,
+    packet_handler: PacketHandler,
+    log_handler: Option<LogHandler>,
+    disconnect_handler: Option<DisconnectHandler>,
+}
+
+impl<'a> UdpServerBuilder<'a> {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            address: "0.0.0.0",
+            port: 4444,
+            peer_timeout: Duration::from_secs(30),
+            error_handler: None,
+            packet_handler: Box::new(|peer, packet| {
+                println!("{} sent {:?}.", peer.address(), packet)
+            }),
+            log_handler: None,
+            disconnect_handler: None,
+        }
+    }
+
+    /// Sets the server address.
+    pub fn address(self, address: &'a str) -> Self {
+        Self { address, ..self }
+    }
+
+    /// Sets the server port.
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Sets how long a peer can stay silent before it is forgotten, 30 seconds by default.
+    pub fn peer_timeout(self, peer_timeout: Duration) -> Self {
+        Self {
+            peer_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the server `error handler`
+    pub fn error_handler(self, handler: ErrorHandler) -> Self {
+        Self {
+            error_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Sets the server `packet handler`
+    pub fn packet_handler(self, handler: PacketHandler) -> Self {
+        Self {
+            packet_handler: handler,
+            ..self
+        }
+    }
+
+    /// Sets the server `logger`
+    pub fn log_handler(self, handler: LogHandler) -> Self {
+        Self {
+            log_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Sets the server `disconnect handler`, called with the address of a peer once it expired.
+    pub fn disconnect_handler(self, handler: DisconnectHandler) -> Self {
+        Self {
+            disconnect_handler: Some(handler),
+            ..self
+        }
+    }
+
+    /// Build the server object.
+    pub fn build(self) -> UdpServer<'a> {
+        UdpServer {
+            address: self.address,
+            port: self.port,
+            peer_timeout: self.peer_timeout,
+            shared: Arc::new(Shared {
+                stopping: AtomicBool::new(false),
+                socket: Mutex::new(None),
+                peers: Mutex::new(HashMap::new()),
+                invalid_datagrams: AtomicU64::new(0),
+            }),
+            error_handler: self.error_handler,
+            packet_handler: self.packet_handler,
+            log_handler: self.log_handler,
+            disconnect_handler: self.disconnect_handler,
+        }
+    }
+}
+
+impl<'a> Default for UdpServerBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
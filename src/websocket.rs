@@ -0,0 +1,202 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use tungstenite::{
+    client::IntoClientRequest,
+    error::UrlError,
+    handshake::{HandshakeError, HandshakeRole},
+    Message, WebSocket,
+};
+
+use crate::{Error, FRAME_HEADER_SIZE};
+
+/// Socket given to tungstenite. Reads only reach the socket once allowed by `fetch`, so reading
+/// from a [WebSocketStream] never blocks while it is locked.
+pub(crate) struct Gate {
+    tcp: TcpStream,
+    fetch: bool,
+}
+
+impl Read for Gate {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !std::mem::take(&mut self.fetch) {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+
+        // A socket timeout must not be mistaken for the closed gate.
+        self.tcp.read(buf).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock => ErrorKind::TimedOut.into(),
+            _ => e,
+        })
+    }
+}
+
+impl Write for Gate {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tcp.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.tcp.flush()
+    }
+}
+
+/// WebSocket connection carrying one encoded [Packet](crate::Packet) per binary message. It is
+/// read and written as a stream of frames, so it works like any other transport.
+pub(crate) struct WebSocketStream {
+    socket: WebSocket<Gate>,
+    received: Vec<u8>,
+    written: Vec<u8>,
+}
+
+impl WebSocketStream {
+    fn new(socket: WebSocket<Gate>) -> Self {
+        Self {
+            socket,
+            received: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Returns the TCP stream under the WebSocket.
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        &self.socket.get_ref().tcp
+    }
+
+    /// Returns the frames of the messages received so far, first reading the socket if `fetch` is
+    /// true. Returns [ErrorKind::WouldBlock] when no complete message was received yet.
+    pub(crate) fn read_frames(
+        &mut self,
+        mut fetch: bool,
+        buf: &mut [u8],
+    ) -> std::io::Result<usize> {
+        while self.received.is_empty() {
+            self.socket.get_mut().fetch = std::mem::take(&mut fetch);
+            let message = self.socket.read();
+            self.socket.get_mut().fetch = false;
+
+            match message {
+                Ok(Message::Binary(payload)) => self.received = frame(&payload)?,
+                Ok(Message::Text(_)) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "text messages cannot carry packets",
+                    ))
+                }
+                Ok(Message::Close(_)) => return Ok(0),
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(0)
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+
+        let size = buf.len().min(self.received.len());
+        buf[..size].copy_from_slice(&self.received[..size]);
+        self.received.drain(..size);
+
+        Ok(size)
+    }
+}
+
+impl Write for WebSocketStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    /// Sends every complete frame written so far as a binary message.
+    fn flush(&mut self) -> std::io::Result<()> {
+        while self.written.len() >= FRAME_HEADER_SIZE {
+            let length = u32::from_le_bytes(self.written[1..FRAME_HEADER_SIZE].try_into().unwrap());
+            let end = FRAME_HEADER_SIZE + length as usize;
+            if self.written.len() < end {
+                break;
+            }
+
+            let mut payload = Vec::with_capacity(end - FRAME_HEADER_SIZE + 1);
+            payload.push(self.written[0]);
+            payload.extend_from_slice(&self.written[FRAME_HEADER_SIZE..end]);
+            self.written.drain(..end);
+
+            self.socket
+                .send(Message::binary(payload))
+                .map_err(io_error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Turns the payload of a binary message, an encoded packet, into a frame.
+fn frame(payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    let (version, packet) = payload
+        .split_first()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "empty binary message"))?;
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + packet.len());
+    frame.push(*version);
+    frame.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    frame.extend_from_slice(packet);
+
+    Ok(frame)
+}
+
+fn io_error(error: tungstenite::Error) -> std::io::Error {
+    match error {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            ErrorKind::BrokenPipe.into()
+        }
+        e => std::io::Error::new(ErrorKind::InvalidData, e),
+    }
+}
+
+/// Drives a handshake to completion, opening the gate every time tungstenite needs more bytes.
+fn handshake<R: HandshakeRole<InternalStream = Gate>>(
+    mut result: Result<R::FinalResult, HandshakeError<R>>,
+) -> Result<R::FinalResult, Error> {
+    loop {
+        match result {
+            Ok(done) => return Ok(done),
+            Err(HandshakeError::Interrupted(mut handshake)) => {
+                handshake.get_mut().get_mut().fetch = true;
+                result = handshake.handshake();
+            }
+            Err(HandshakeError::Failure(tungstenite::Error::Io(e))) => return Err(e.into()),
+            Err(HandshakeError::Failure(e)) => return Err(Error::WebSocket(e)),
+        }
+    }
+}
+
+/// Answers the WebSocket upgrade request sent by a client.
+pub(crate) fn accept(tcp: TcpStream) -> Result<WebSocketStream, Error> {
+    let socket = handshake(tungstenite::accept(Gate { tcp, fetch: true }))?;
+
+    Ok(WebSocketStream::new(socket))
+}
+
+/// Connects to the server at a `ws://` url and upgrades the connection.
+pub(crate) fn connect(url: &str) -> Result<WebSocketStream, Error> {
+    let request = url.into_client_request().map_err(Error::WebSocket)?;
+    let uri = request.uri();
+    if uri.scheme_str() != Some("ws") {
+        return Err(Error::WebSocket(tungstenite::Error::Url(
+            UrlError::UnsupportedUrlScheme,
+        )));
+    }
+
+    let address = format!(
+        "{}:{}",
+        uri.host().unwrap_or_default(),
+        uri.port_u16().unwrap_or(80)
+    );
+    let tcp = TcpStream::connect(&address).map_err(|source| Error::Connect { address, source })?;
+    let (socket, _) = handshake(tungstenite::client(request, Gate { tcp, fetch: true }))?;
+
+    Ok(WebSocketStream::new(socket))
+}